@@ -0,0 +1,62 @@
+/*
+ * C interface of the HTTPS Everywhere Core Library, exposed by the `cffi` feature.  Link against
+ * the static or dynamic library built with
+ * `cargo rustc --release --features cffi --crate-type staticlib,cdylib`.  See the `ffi` module
+ * for the documentation of each function.
+ */
+
+#ifndef HTTPS_EVERYWHERE_H
+#define HTTPS_EVERYWHERE_H
+
+#include <stdbool.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The action to take for a URL, returned within a `RewriteResult` by `https_everywhere_rewrite` */
+typedef enum RewriteResultAction {
+    /* Leave the request unchanged */
+    HTTPS_EVERYWHERE_ACTION_NO_OP = 0,
+    /* Redirect the request to the URL of the result */
+    HTTPS_EVERYWHERE_ACTION_REWRITE_URL = 1,
+    /* Cancel the request */
+    HTTPS_EVERYWHERE_ACTION_CANCEL_REQUEST = 2,
+    /* The request is likely part of a redirect loop, and should be left unchanged */
+    HTTPS_EVERYWHERE_ACTION_REDIRECT_LOOP_WARNING = 3,
+    /* The handle or URL given was invalid, or the URL could not be rewritten */
+    HTTPS_EVERYWHERE_ACTION_ERROR = 4,
+} RewriteResultAction;
+
+/* An opaque rewriter handle */
+typedef struct Rewriter Rewriter;
+
+/*
+ * The result of `https_everywhere_rewrite`.  A non-null `url` must be released with
+ * `https_everywhere_string_free`
+ */
+typedef struct RewriteResult {
+    RewriteResultAction action;
+    char *url;
+} RewriteResult;
+
+/*
+ * Returns a handle to a stateless rewriter with the JSON string of rulesets given, or null if the
+ * rulesets are not valid.  The handle must be destroyed with `https_everywhere_rewriter_free`
+ */
+Rewriter *https_everywhere_rewriter_new(const char *rulesets_json, bool enable_mixed_rulesets);
+
+/* Destroy a rewriter handle.  Null handles are ignored */
+void https_everywhere_rewriter_free(Rewriter *handle);
+
+/* Returns the action to take for a URL */
+RewriteResult https_everywhere_rewrite(Rewriter *handle, const char *url);
+
+/* Release a string returned by the library.  Null strings are ignored */
+void https_everywhere_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* HTTPS_EVERYWHERE_H */
@@ -0,0 +1,224 @@
+//! A C-compatible interface for rewriting URLs, for embedding the library in applications not
+//! written in Rust.  A rewriter handle is created from a JSON string of rulesets with
+//! `https_everywhere_rewriter_new`, URLs are rewritten with `https_everywhere_rewrite`, and the
+//! handle is destroyed with `https_everywhere_rewriter_free`.  Strings returned by the library are
+//! owned by the caller, and must be released with `https_everywhere_string_free`
+//!
+//! A static or dynamic library to link against is built with
+//! `cargo rustc --release --features cffi --crate-type staticlib,cdylib`.  The declarations of
+//! these functions for C are in `include/https_everywhere.h`, which must be kept in sync with this
+//! module; the variants of `RewriteResultAction` are prefixed there with
+//! `HTTPS_EVERYWHERE_ACTION_`
+//!
+//! No pointer given to these functions is trusted to be non-null or to point to valid UTF-8:
+//! invalid input results in a null handle or `RewriteResultAction::Error`, rather than undefined
+//! behavior
+
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use crate::{RuleSets, Rewriter, rewriter::RewriteAction, rulesets::shared};
+
+/// The action to take for a URL, returned within a `RewriteResult` by `https_everywhere_rewrite`
+#[repr(C)]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RewriteResultAction {
+    /// Leave the request unchanged
+    NoOp = 0,
+    /// Redirect the request to the URL of the result
+    RewriteUrl = 1,
+    /// Cancel the request
+    CancelRequest = 2,
+    /// The request is likely part of a redirect loop, and should be left unchanged
+    RedirectLoopWarning = 3,
+    /// The handle or URL given was invalid, or the URL could not be rewritten
+    Error = 4,
+}
+
+/// The result of `https_everywhere_rewrite`.  `url` is the URL to redirect to for
+/// `RewriteResultAction::RewriteUrl`, the URL which was evaluated for the other actions, or null
+/// if there is none.  A non-null `url` must be released with `https_everywhere_string_free`
+#[repr(C)]
+#[derive(Debug)]
+pub struct RewriteResult {
+    pub action: RewriteResultAction,
+    pub url: *mut c_char,
+}
+
+impl RewriteResult {
+    fn error() -> RewriteResult {
+        RewriteResult { action: RewriteResultAction::Error, url: ptr::null_mut() }
+    }
+
+    fn from_action(action: RewriteAction) -> RewriteResult {
+        let (action, url) = match action {
+            RewriteAction::NoOp(url) => (RewriteResultAction::NoOp, url),
+            RewriteAction::RewriteUrl(url) => (RewriteResultAction::RewriteUrl, Some(url)),
+            RewriteAction::CancelRequest(url) => (RewriteResultAction::CancelRequest, url),
+            RewriteAction::RedirectLoopWarning(url) => (RewriteResultAction::RedirectLoopWarning, url),
+        };
+        match url.map(CString::new) {
+            None => RewriteResult { action, url: ptr::null_mut() },
+            Some(Ok(url)) => RewriteResult { action, url: url.into_raw() },
+            Some(Err(_)) => RewriteResult::error(),
+        }
+    }
+}
+
+/// Returns the UTF-8 string pointed to, or `None` if the pointer is null or the string is not
+/// valid UTF-8
+///
+/// # Safety
+///
+/// `s` must be null or point to a nul-terminated string
+unsafe fn str_from_ptr<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    CStr::from_ptr(s).to_str().ok()
+}
+
+/// Returns a handle to a stateless rewriter with the rulesets specified, or null if the rulesets
+/// are not a valid JSON string.  The handle must be destroyed with
+/// `https_everywhere_rewriter_free`
+///
+/// # Arguments
+///
+/// * `rulesets_json` - A nul-terminated JSON string of rulesets, as accepted by
+///   `RuleSets::add_all_from_json_string`
+/// * `enable_mixed_rulesets` - Whether mixed-content rulesets are active
+///
+/// # Safety
+///
+/// `rulesets_json` must be null or point to a nul-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn https_everywhere_rewriter_new(rulesets_json: *const c_char, enable_mixed_rulesets: bool) -> *mut Rewriter {
+    let rulesets_json = match str_from_ptr(rulesets_json) {
+        Some(rulesets_json) => rulesets_json,
+        None => return ptr::null_mut(),
+    };
+    let rewriter = panic::catch_unwind(|| {
+        let mut rs = RuleSets::new();
+        rs.try_add_all_from_json_string(rulesets_json, enable_mixed_rulesets, &HashMap::new(), &None).ok()?;
+        Some(Rewriter::new_stateless(shared::new(rs)))
+    });
+    match rewriter {
+        Ok(Some(rewriter)) => Box::into_raw(Box::new(rewriter)),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Destroy a rewriter handle.  Null handles are ignored
+///
+/// # Safety
+///
+/// `handle` must be null or a handle returned by `https_everywhere_rewriter_new`, which has not
+/// already been destroyed
+#[no_mangle]
+pub unsafe extern "C" fn https_everywhere_rewriter_free(handle: *mut Rewriter) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Returns the action to take for a URL.  `RewriteResultAction::Error` is returned if the handle
+/// is null, the URL is null or not valid UTF-8, or the URL could not be rewritten
+///
+/// # Arguments
+///
+/// * `handle` - A rewriter handle returned by `https_everywhere_rewriter_new`
+/// * `url` - A nul-terminated URL to determine the action for
+///
+/// # Safety
+///
+/// `handle` must be null or a live handle returned by `https_everywhere_rewriter_new`, not used
+/// concurrently by another thread, and `url` must be null or point to a nul-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn https_everywhere_rewrite(handle: *mut Rewriter, url: *const c_char) -> RewriteResult {
+    let rewriter = match handle.as_mut() {
+        Some(rewriter) => rewriter,
+        None => return RewriteResult::error(),
+    };
+    let url = match str_from_ptr(url) {
+        Some(url) => url,
+        None => return RewriteResult::error(),
+    };
+    match panic::catch_unwind(AssertUnwindSafe(|| rewriter.rewrite_url(url))) {
+        Ok(Ok(action)) => RewriteResult::from_action(action),
+        _ => RewriteResult::error(),
+    }
+}
+
+/// Release a string returned by the library.  Null strings are ignored
+///
+/// # Safety
+///
+/// `s` must be null or a string returned by the library, which has not already been released
+#[no_mangle]
+pub unsafe extern "C" fn https_everywhere_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn rewriter() -> *mut Rewriter {
+        let rulesets_json = CString::new(fs::read_to_string("tests/mock_rulesets.json").unwrap()).unwrap();
+        unsafe { https_everywhere_rewriter_new(rulesets_json.as_ptr(), true) }
+    }
+
+    fn rewrite(handle: *mut Rewriter, url: &[u8]) -> (RewriteResultAction, Option<String>) {
+        let url = CString::new(url).unwrap();
+        unsafe {
+            let result = https_everywhere_rewrite(handle, url.as_ptr());
+            let url = str_from_ptr(result.url).map(String::from);
+            https_everywhere_string_free(result.url);
+            (result.action, url)
+        }
+    }
+
+    #[test]
+    fn rewrites_through_ffi() {
+        let handle = rewriter();
+        assert!(!handle.is_null());
+
+        assert_eq!(rewrite(handle, b"http://freerangekitten.com/"),
+            (RewriteResultAction::RewriteUrl, Some(String::from("https://freerangekitten.com/"))));
+        assert_eq!(rewrite(handle, b"http://fake-example.com/"),
+            (RewriteResultAction::NoOp, Some(String::from("http://fake-example.com/"))));
+
+        unsafe { https_everywhere_rewriter_free(handle) };
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        let invalid_json = CString::new("not json").unwrap();
+        unsafe {
+            assert!(https_everywhere_rewriter_new(ptr::null(), true).is_null());
+            assert!(https_everywhere_rewriter_new(invalid_json.as_ptr(), true).is_null());
+        }
+
+        let url = CString::new("http://freerangekitten.com/").unwrap();
+        let result = unsafe { https_everywhere_rewrite(ptr::null_mut(), url.as_ptr()) };
+        assert_eq!(result.action, RewriteResultAction::Error);
+        assert!(result.url.is_null());
+
+        let handle = rewriter();
+        let result = unsafe { https_everywhere_rewrite(handle, ptr::null()) };
+        assert_eq!(result.action, RewriteResultAction::Error);
+        assert_eq!(rewrite(handle, b"http://\xff.com/"), (RewriteResultAction::Error, None));
+
+        unsafe {
+            https_everywhere_rewriter_free(handle);
+            https_everywhere_rewriter_free(ptr::null_mut());
+            https_everywhere_string_free(ptr::null_mut());
+        }
+    }
+}
@@ -24,17 +24,31 @@ cfg_if::cfg_if! {
     }
 }
 
+#[cfg(feature="cffi")]
+pub mod ffi;
+
+#[cfg(feature="wasm")]
+pub mod wasm;
+
+#[cfg(target_arch="wasm32")]
+mod wasm32;
+
+#[cfg(all(feature="updater", target_arch="wasm32"))]
+compile_error!("The `updater` feature depends on openssl and http_req, which do not build for wasm32.  Build with `--no-default-features` and the features needed, such as `rewriter`");
+
 cfg_if::cfg_if! {
-    if #[cfg(feature="get_simple_rules_ending_with")] {
+    if #[cfg(any(feature="rewriter",feature="get_simple_rules_ending_with"))] {
         pub mod regex;
         pub use crate::regex::RegEx;
     }
 }
 
-#[cfg(any(feature="settings",feature="updater",feature="rewriter"))]
+#[cfg(any(feature="settings",feature="updater",feature="rewriter",feature="async-storage"))]
 mod storage;
-#[cfg(any(feature="settings",feature="updater",feature="rewriter"))]
-pub use storage::Storage;
+#[cfg(any(feature="settings",feature="updater",feature="rewriter",feature="async-storage"))]
+pub use storage::{Storage, StorageValue};
+#[cfg(feature="async-storage")]
+pub use storage::{AsyncStorage, SyncStorageAdapter};
 
 #[cfg(any(feature="rewriter",feature="updater"))]
 #[macro_use]
@@ -1,17 +1,73 @@
-pub trait RegEx {
-    fn new(re: &str) -> Self;
+/// A regular expression engine.  The rulesets and rewriter match rule `from` regexes, exclusions,
+/// scopes and cookierules through this trait, so that embedders may substitute a different engine
+/// for the `regex` crate, which implements it when the `rewriter` feature is enabled
+pub trait RegEx: Sized {
+    /// Compile the given regex, panicking if it is invalid
+    fn new(re: &str) -> Self {
+        match Self::try_new(re) {
+            Some(regex) => regex,
+            None => panic!("Invalid regex: {}", re)
+        }
+    }
+
+    /// Compile the given regex, returning `None` if it is invalid
+    fn try_new(re: &str) -> Option<Self>;
+
+    /// Return whether the regex matches anywhere in the given text
     fn is_match(&self, text: &str) -> bool;
+
+    /// Replace all non-overlapping matches in the given text with the replacement, in which `$1`
+    /// and so on refer to capture groups
+    fn replace_all(&self, text: &str, replacement: &str) -> String;
 }
 
-#[cfg(all(test,feature="get_simple_rules_ending_with"))]
-use regex::Regex;
-#[cfg(all(test,feature="get_simple_rules_ending_with"))]
+/// The maximum size, in bytes, of a compiled regex.  Rule patterns come from update channels, so
+/// this bounds the memory a pathological pattern can take to compile
+pub const REGEX_SIZE_LIMIT: usize = 1024 * 1024;
+
+/// The maximum size, in bytes, of the lazy DFA cache used when matching a regex.  Once the cache
+/// is exhausted, matching falls back to slower engines which still run in linear time, so a
+/// single pattern can't hang the rewrite path
+pub const REGEX_DFA_SIZE_LIMIT: usize = 1024 * 1024;
+
+#[cfg(any(test,feature="rewriter"))]
+use regex::{Regex, RegexBuilder};
+
+/// Compile the given regex within `REGEX_SIZE_LIMIT` and `REGEX_DFA_SIZE_LIMIT`, returning the
+/// error if it is invalid or exceeds the limits
+#[cfg(any(test,feature="rewriter"))]
+pub(crate) fn build_regex(re: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(re)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_DFA_SIZE_LIMIT)
+        .build()
+}
+
+#[cfg(any(test,feature="rewriter"))]
 impl RegEx for Regex {
-    fn new(re: &str) -> Self {
-        Regex::new(re).unwrap()
+    /// Compile the given regex within `REGEX_SIZE_LIMIT` and `REGEX_DFA_SIZE_LIMIT`, returning
+    /// `None` if it is invalid or exceeds the limits
+    fn try_new(re: &str) -> Option<Self> {
+        build_regex(re).ok()
     }
 
     fn is_match(&self, text: &str) -> bool {
         self.is_match(text)
     }
+
+    fn replace_all(&self, text: &str, replacement: &str) -> String {
+        self.replace_all(text, replacement).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_regexes_exceeding_size_limit() {
+        assert!(<Regex as RegEx>::try_new(r"^http://(www\.)?example\.com/").is_some());
+        assert!(<Regex as RegEx>::try_new(r"\w{1000}").is_none());
+        assert!(<Regex as RegEx>::try_new(r"(unclosed").is_none());
+    }
 }
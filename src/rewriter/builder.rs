@@ -0,0 +1,240 @@
+use lru::LruCache;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::error::Error;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, atomic::AtomicUsize, Mutex};
+
+use crate::{RegEx, settings::ThreadSafeSettings, rulesets::ThreadSafeRuleSets};
+use super::{Rewriter, ThreadSafeBloomVec, DEFAULT_DECISION_CAPACITY};
+
+/// The default number of cookie domains whose safety is cached
+pub const DEFAULT_CACHE_SIZE: usize = 250; // 250 is somewhat arbitrary
+
+/// The default number of recent rewrites remembered to detect redirect loops
+pub const DEFAULT_HISTORY_WINDOW: usize = 15;
+
+/// The default number of identical rewrites within the history window which are considered a
+/// redirect loop
+pub const DEFAULT_LOOP_THRESHOLD: usize = 8;
+
+/// An invalid option given to a RewriterBuilder
+#[derive(Debug, PartialEq, Clone)]
+pub enum RewriterBuilderError {
+    /// The cache size is zero
+    ZeroCacheSize,
+    /// The history window is zero
+    ZeroHistoryWindow,
+    /// The loop threshold is zero, or larger than the history window, so a redirect loop would
+    /// always or never be detected
+    InvalidLoopThreshold { loop_threshold: usize, history_window: usize },
+}
+
+impl fmt::Display for RewriterBuilderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RewriterBuilderError::ZeroCacheSize => write!(f, "Cache size must be non-zero"),
+            RewriterBuilderError::ZeroHistoryWindow => write!(f, "History window must be non-zero"),
+            RewriterBuilderError::InvalidLoopThreshold { loop_threshold, history_window } =>
+                write!(f, "Loop threshold {} must be between 1 and the history window {}", loop_threshold, history_window),
+        }
+    }
+}
+
+impl Error for RewriterBuilderError {}
+
+/// A RewriterBuilder constructs a Rewriter, filling in defaults for any options which are not
+/// set.  The rulesets are always required, and settings unless the rewriter is stateless
+pub struct RewriterBuilder {
+    rulesets: ThreadSafeRuleSets,
+    settings: Option<ThreadSafeSettings>,
+    blooms: ThreadSafeBloomVec,
+    cache_size: usize,
+    history_window: usize,
+    loop_threshold: usize,
+    track_history: bool,
+    max_candidate_rulesets: Option<usize>,
+}
+
+impl RewriterBuilder {
+    /// Returns a builder for a rewriter with the rulesets and settings specified
+    ///
+    /// # Arguments
+    ///
+    /// * `rulesets` - An instance of RuleSets for rewriting URLs, shared as ThreadSafeRuleSets
+    /// * `settings` - A settings object to query current state, wrapped in an Arc<Mutex>
+    pub fn new(rulesets: ThreadSafeRuleSets, settings: ThreadSafeSettings) -> RewriterBuilder {
+        RewriterBuilder::with_settings(rulesets, Some(settings))
+    }
+
+    /// Returns a builder for a stateless rewriter with the rulesets specified.  See
+    /// `Rewriter::new_stateless`
+    ///
+    /// # Arguments
+    ///
+    /// * `rulesets` - An instance of RuleSets for rewriting URLs, shared as ThreadSafeRuleSets
+    pub fn new_stateless(rulesets: ThreadSafeRuleSets) -> RewriterBuilder {
+        RewriterBuilder::with_settings(rulesets, None)
+    }
+
+    fn with_settings(rulesets: ThreadSafeRuleSets, settings: Option<ThreadSafeSettings>) -> RewriterBuilder {
+        RewriterBuilder {
+            rulesets,
+            settings,
+            blooms: Arc::new(Mutex::new(vec![])),
+            cache_size: DEFAULT_CACHE_SIZE,
+            history_window: DEFAULT_HISTORY_WINDOW,
+            loop_threshold: DEFAULT_LOOP_THRESHOLD,
+            track_history: true,
+            max_candidate_rulesets: None,
+        }
+    }
+
+    /// Set the bloom filters of upgradeable domains.  Defaults to an empty vec
+    pub fn blooms(mut self, blooms: ThreadSafeBloomVec) -> RewriterBuilder {
+        self.blooms = blooms;
+        self
+    }
+
+    /// Set the number of cookie domains whose safety is cached.  Defaults to `DEFAULT_CACHE_SIZE`
+    pub fn cache_size(mut self, cache_size: usize) -> RewriterBuilder {
+        self.cache_size = cache_size;
+        self
+    }
+
+    /// Set the number of recent rewrites remembered to detect redirect loops.  Defaults to
+    /// `DEFAULT_HISTORY_WINDOW`
+    pub fn history_window(mut self, history_window: usize) -> RewriterBuilder {
+        self.history_window = history_window;
+        self
+    }
+
+    /// Set the number of identical rewrites within the history window which are considered a
+    /// redirect loop.  Defaults to `DEFAULT_LOOP_THRESHOLD`
+    pub fn loop_threshold(mut self, loop_threshold: usize) -> RewriterBuilder {
+        self.loop_threshold = loop_threshold;
+        self
+    }
+
+    /// Set whether to track the history of rewrites to detect redirect loops.  See
+    /// `Rewriter::set_track_history`.  Defaults to true
+    pub fn track_history(mut self, track_history: bool) -> RewriterBuilder {
+        self.track_history = track_history;
+        self
+    }
+
+    /// Set the maximum number of potentially applicable rulesets evaluated for a URL.  See
+    /// `Rewriter::set_max_candidate_rulesets`.  Defaults to `None`, for no limit
+    pub fn max_candidate_rulesets(mut self, max_candidate_rulesets: Option<usize>) -> RewriterBuilder {
+        self.max_candidate_rulesets = max_candidate_rulesets;
+        self
+    }
+
+    /// Returns the rewriter, or an error if the options are invalid
+    pub fn build(self) -> Result<Rewriter, RewriterBuilderError> {
+        self.build_with_regex_engine()
+    }
+
+    /// Returns the rewriter, which matches regexes with the engine `R`, or an error if the
+    /// options are invalid
+    pub fn build_with_regex_engine<R: RegEx + Send + Sync + 'static>(self) -> Result<Rewriter<R>, RewriterBuilderError> {
+        let cache_size = NonZeroUsize::new(self.cache_size).ok_or(RewriterBuilderError::ZeroCacheSize)?;
+        if self.history_window == 0 {
+            return Err(RewriterBuilderError::ZeroHistoryWindow);
+        }
+        if self.loop_threshold == 0 || self.loop_threshold > self.history_window {
+            return Err(RewriterBuilderError::InvalidLoopThreshold {
+                loop_threshold: self.loop_threshold,
+                history_window: self.history_window,
+            });
+        }
+
+        Ok(Rewriter {
+            rulesets: self.rulesets,
+            blooms: self.blooms,
+            settings: self.settings,
+            rewrite_count: AtomicUsize::new(0),
+            rewrite_counts_by_host: None,
+            cookie_host_safety_cache: LruCache::new(cache_size),
+            rewrite_history: VecDeque::with_capacity(self.history_window),
+            history_window: self.history_window,
+            loop_threshold: self.loop_threshold,
+            track_history: self.track_history,
+            scope_regexes: HashMap::new(),
+            decisions: None,
+            decision_capacity: DEFAULT_DECISION_CAPACITY,
+            max_candidate_rulesets: self.max_candidate_rulesets,
+        })
+    }
+}
+
+#[cfg(all(test,feature="add_rulesets"))]
+mod tests {
+    use super::*;
+    use crate::rulesets::shared;
+    use crate::{RuleSets, Settings};
+    use crate::rewriter::{NoOpReason, RewriteAction};
+    use crate::rulesets::tests as rulesets_tests;
+    use crate::storage::tests::mock_storage::TestStorage;
+
+    fn builder() -> RewriterBuilder {
+        let mut rs = RuleSets::new();
+        rulesets_tests::add_mock_rulesets(&mut rs);
+        let s: ThreadSafeSettings = Arc::new(Mutex::new(Settings::new(Arc::new(Mutex::new(TestStorage)))));
+        RewriterBuilder::new(shared::new(rs), s)
+    }
+
+    #[test]
+    fn validates_options() {
+        assert_eq!(builder().cache_size(0).build().err(), Some(RewriterBuilderError::ZeroCacheSize));
+        assert_eq!(builder().history_window(0).build().err(), Some(RewriterBuilderError::ZeroHistoryWindow));
+        assert_eq!(builder().history_window(4).build().err(), Some(RewriterBuilderError::InvalidLoopThreshold { loop_threshold: 8, history_window: 4 }));
+        assert_eq!(builder().loop_threshold(0).build().err(), Some(RewriterBuilderError::InvalidLoopThreshold { loop_threshold: 0, history_window: 15 }));
+        assert!(builder().cache_size(1).build().is_ok());
+    }
+
+    #[test]
+    fn limits_candidate_rulesets() {
+        let mut rs = RuleSets::new();
+        rs.add_all_from_json_string(r#"[
+            {"name": "Unrelated", "target": ["www.example.*"], "rule": [{"from": "^http://unrelated\\.", "to": "https://unrelated."}]},
+            {"name": "Example", "target": ["*.example.com"], "rule": [{"from": "^http:", "to": "https:"}]}
+        ]"#, true, &std::collections::HashMap::new(), &None);
+        let rs = shared::new(rs);
+
+        let mut rw = RewriterBuilder::new_stateless(Arc::clone(&rs)).max_candidate_rulesets(Some(1)).build().unwrap();
+        assert_eq!(
+            rw.rewrite_url_reason("http://www.example.com/").unwrap(),
+            (RewriteAction::NoOp(Some(String::from("http://www.example.com/"))), Some(NoOpReason::TooManyCandidates)));
+
+        rw.set_max_candidate_rulesets(Some(2));
+        assert_eq!(
+            rw.rewrite_url("http://www.example.com/").unwrap(),
+            RewriteAction::RewriteUrl(String::from("https://www.example.com/")));
+
+        // Inactive rulesets are not counted as candidates
+        let mut active_states = std::collections::HashMap::new();
+        active_states.insert(String::from("Unrelated"), false);
+        let mut rs = RuleSets::new();
+        rs.add_all_from_json_string(r#"[
+            {"name": "Unrelated", "target": ["www.example.*"], "rule": [{"from": "^http://unrelated\\.", "to": "https://unrelated."}]},
+            {"name": "Example", "target": ["*.example.com"], "rule": [{"from": "^http:", "to": "https:"}]}
+        ]"#, true, &active_states, &None);
+        let mut rw = RewriterBuilder::new_stateless(shared::new(rs)).max_candidate_rulesets(Some(1)).build().unwrap();
+        assert_eq!(
+            rw.rewrite_url("http://www.example.com/").unwrap(),
+            RewriteAction::RewriteUrl(String::from("https://www.example.com/")));
+    }
+
+    #[test]
+    fn uses_configured_loop_threshold() {
+        let mut rw = builder().history_window(3).loop_threshold(2).build().unwrap();
+
+        assert_eq!(
+            rw.rewrite_url("http://freerangekitten.com/").unwrap(),
+            RewriteAction::RewriteUrl(String::from("https://freerangekitten.com/")));
+        assert_eq!(
+            rw.rewrite_url("http://freerangekitten.com/").unwrap(),
+            RewriteAction::RedirectLoopWarning(Some(String::from("http://freerangekitten.com/"))));
+    }
+}
@@ -1,21 +1,53 @@
+mod snapshot;
+#[cfg(any(feature="rewriter",feature="updater"))]
+pub(crate) mod shared;
+#[cfg(feature="potentially_applicable")]
+mod target_index;
+
 use std::sync::Arc;
-use std::collections::BTreeMap;
+#[cfg(feature="potentially_applicable")]
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
 
 #[cfg(feature="add_rulesets")]
 use serde_json::Value;
 #[cfg(feature="add_rulesets")]
 use crate::strings::ERROR_SERDE_PARSE;
-#[cfg(feature="get_simple_rules_ending_with")]
+#[cfg(any(feature="rewriter",feature="get_simple_rules_ending_with"))]
 use crate::RegEx;
+#[cfg(feature="rewriter")]
+use crate::regex::build_regex;
 #[cfg(feature="add_rulesets")]
 use std::collections::HashMap;
+#[cfg(feature="add_rulesets")]
+use serde::de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+#[cfg(feature="rewriter")]
+use std::any::Any;
+#[cfg(feature="rewriter")]
+use std::sync::OnceLock;
+#[cfg(feature="add_rulesets")]
+use std::io::{BufReader, Read};
+#[cfg(feature="add_rulesets_gzip")]
+use flate2::read::GzDecoder;
+#[cfg(feature="potentially_applicable")]
+use target_index::{Candidate, TargetIndex};
 #[cfg(any(all(test,feature="get_simple_rules_ending_with",feature="add_rulesets"),feature="rewriter"))]
 use regex::Regex;
 
-#[cfg(any(feature="rewriter",feature="updater"))]
-use std::sync::Mutex;
-#[cfg(any(feature="rewriter",feature="updater"))]
-pub type ThreadSafeRuleSets = Arc<Mutex<RuleSets>>;
+cfg_if::cfg_if! {
+    if #[cfg(all(feature="arc-swap",any(feature="rewriter",feature="updater")))] {
+        /// Rulesets shared between threads.  Rewriting reads a snapshot of the rulesets without
+        /// locking, while the updater builds new rulesets and atomically swaps them in
+        pub type ThreadSafeRuleSets = Arc<arc_swap::ArcSwap<RuleSets>>;
+    } else if #[cfg(any(feature="rewriter",feature="updater"))] {
+        use std::sync::RwLock;
+        /// Rulesets shared between threads.  Rewriting only reads the rulesets, so many threads
+        /// may rewrite at once, while the updater takes the write lock to apply new rulesets
+        pub type ThreadSafeRuleSets = Arc<RwLock<RuleSets>>;
+    }
+}
 
 #[cfg(any(all(test,feature="add_rulesets"),feature="updater"))]
 pub(crate) const ENABLE_MIXED_RULESETS: bool = true;
@@ -59,7 +91,7 @@ const JSON_STRINGS: StaticJsonStrings = StaticJsonStrings {
 };
 
 /// A Rule is used to rewrite URLs from some regular expression to some string
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Rule {
     Trivial,
     NonTrivial(String, String)
@@ -97,14 +129,165 @@ impl Rule {
         }
     }
 
+    /// Test a rule with the from regex and replacement string specified against a URL, without
+    /// building any rulesets.  The rule is applied as a ruleset consisting only of it would apply
+    /// it, returning the rewritten URL, `None` if the rule does not rewrite the URL, or the error
+    /// compiling the from regex
+    ///
+    /// # Arguments
+    ///
+    /// * `from_regex` - A string that will be compiled to regex indicating the URL to replace
+    /// * `to` - A string indicating the replacement value
+    /// * `url` - The URL to test the rule against
+    #[cfg(feature="rewriter")]
+    pub fn test(from_regex: &str, to: &str, url: &str) -> Result<Option<String>, regex::Error> {
+        let rule = Rule::new(from_regex.to_string(), to.to_string());
+        if let Rule::NonTrivial(from_regex, _) = &rule {
+            build_regex(from_regex)?;
+        }
+        let mut ruleset = RuleSet::new(String::from("Rule test"), Arc::new(None));
+        ruleset.rules.push(rule);
+        Ok(ruleset.apply::<Regex>(url))
+    }
+
+    /// Returns the replacement string with each unbraced numbered backreference, such as `$1`,
+    /// braced as `${1}`.  Rulesets are written for JavaScript, where `$1a` refers to the first
+    /// group followed by `a`, whereas the regex crate would read it as a reference to a group named
+    /// `1a`.  As in JavaScript, two digits are taken as one reference only if the from regex has
+    /// that many groups
+    ///
+    /// # Arguments
+    ///
+    /// * `from_regex` - A string that will be compiled to regex indicating the URL to replace
+    /// * `to` - A string indicating the replacement value
+    #[cfg(feature="add_rulesets")]
+    fn normalize_backreferences(from_regex: &str, to: &str) -> String {
+        let (group_count, _) = capture_groups(from_regex);
+        let mut normalized = String::with_capacity(to.len());
+        let mut rest = to;
+        while let Some(index) = rest.find('$') {
+            normalized.push_str(&rest[..index + 1]);
+            rest = &rest[index + 1..];
+            if let Some(escaped) = rest.strip_prefix('$') {
+                normalized.push('$');
+                rest = escaped;
+                continue;
+            }
+            let digits = rest.bytes().take(2).take_while(u8::is_ascii_digit).count();
+            let digits = match rest[..digits].parse::<usize>() {
+                Ok(group) if digits == 2 && group > group_count => 1,
+                _ => digits,
+            };
+            if digits > 0 {
+                normalized.push_str(&format!("{{{}}}", &rest[..digits]));
+                rest = &rest[digits..];
+            }
+        }
+        normalized.push_str(rest);
+        normalized
+    }
+
+    /// Returns whether every backreference in the replacement string, such as `$1`, `${1}` or
+    /// `$name`, refers to a capture group of the from regex.  References are read the way the
+    /// regex crate reads them, so an unbraced reference extends over every following letter, digit
+    /// and underscore: `$1a` refers to the group named `1a`.  A reference to a missing group would
+    /// otherwise be replaced with an empty string when the rule is applied
+    ///
+    /// # Arguments
+    ///
+    /// * `from_regex` - A string that will be compiled to regex indicating the URL to replace
+    /// * `to` - A string indicating the replacement value
+    #[cfg(feature="add_rulesets")]
+    fn has_valid_backreferences(from_regex: &str, to: &str) -> bool {
+        let (group_count, group_names) = capture_groups(from_regex);
+        let mut rest = to;
+        while let Some(index) = rest.find('$') {
+            rest = &rest[index + 1..];
+            if let Some(escaped) = rest.strip_prefix('$') {
+                rest = escaped;
+                continue;
+            }
+            let name = match rest.strip_prefix('{') {
+                Some(braced) => match braced.find('}') {
+                    Some(end) => {
+                        rest = &braced[end + 1..];
+                        &braced[..end]
+                    },
+                    None => continue,
+                },
+                None => {
+                    let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+                    let name = &rest[..end];
+                    rest = &rest[end..];
+                    name
+                },
+            };
+            if name.is_empty() {
+                continue;
+            }
+            let valid = match name.parse::<usize>() {
+                Ok(group) => group <= group_count,
+                Err(_) => group_names.contains(&name),
+            };
+            if !valid {
+                return false;
+            }
+        }
+        true
+    }
+
+}
+
+/// Returns the number of capture groups in a regex, named or not, along with the names of the named
+/// ones.  Escaped parentheses and those within character classes do not open groups
+#[cfg(feature="add_rulesets")]
+fn capture_groups(regex: &str) -> (usize, Vec<&str>) {
+    let mut count = 0;
+    let mut names = vec![];
+    let mut class_depth = 0;
+    let mut chars = regex.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            },
+            '[' => class_depth += 1,
+            ']' if class_depth > 0 => class_depth -= 1,
+            '(' if class_depth == 0 => {
+                let rest = &regex[index + 1..];
+                let name_start = if rest.starts_with("?P<") {
+                    Some(3)
+                } else if rest.starts_with("?<") && !rest.starts_with("?<=") && !rest.starts_with("?<!") {
+                    Some(2)
+                } else {
+                    None
+                };
+                if let Some(name_start) = name_start {
+                    if let Some(name_end) = rest[name_start..].find('>') {
+                        names.push(&rest[name_start..name_start + name_end]);
+                    }
+                    count += 1;
+                } else if !rest.starts_with('?') {
+                    count += 1;
+                }
+            },
+            _ => {},
+        }
+    }
+    (count, names)
 }
 
 
-/// A CookieRule is used to secure cookies which conform to some name and host constraints
-#[derive(Debug)]
+/// A CookieRule is used to secure cookies which conform to some name and host constraints.  When
+/// the `rewriter` feature is enabled, the regexes are compiled once on creation, or on first use
+/// with a regex engine other than `regex::Regex`, so `host_regex` and `name_regex` should not be
+/// modified afterwards
+#[derive(Clone)]
 pub struct CookieRule {
     pub host_regex: String, // RegExp
-    pub name_regex: String // RegExp
+    pub name_regex: String, // RegExp
+    #[cfg(feature="rewriter")]
+    compiled: OnceLock<Arc<dyn Any + Send + Sync>>,
 }
 
 impl CookieRule {
@@ -116,16 +299,80 @@ impl CookieRule {
     /// * `host_regex` - A string that will be compiled to regex indicating the host of the cookie
     /// * `name_regex` - A string that will be compiled to regex indicating the name of the cookie
     pub fn new(host_regex: String, name_regex: String) -> CookieRule {
-        CookieRule {
+        let cookierule = CookieRule {
+            #[cfg(feature="rewriter")]
+            compiled: OnceLock::new(),
             host_regex,
-            name_regex
+            name_regex,
+        };
+        #[cfg(feature="rewriter")]
+        let _ = cookierule.compiled.set(Arc::new(Self::compile::<Regex>(&cookierule.host_regex, &cookierule.name_regex)));
+        cookierule
+    }
+
+    /// Compile the host and name regexes so that they must match the whole domain and name, or
+    /// return `None` if either is invalid or empty
+    #[cfg(feature="rewriter")]
+    fn compile<R: RegEx>(host_regex: &str, name_regex: &str) -> Option<(R, R)> {
+        if host_regex.is_empty() || name_regex.is_empty() {
+            return None;
+        }
+        let full_match = |regex: &str| R::try_new(&format!("^(?:{})$", regex));
+        Some((full_match(host_regex)?, full_match(name_regex)?))
+    }
+
+    /// Return whether a cookie with the given domain and name is covered by this cookierule,
+    /// matching with the regex engine `R`.  As in the extension, the host and name regexes must
+    /// match the whole domain and name, so a host regex of `example\.com` does not match
+    /// `notexample.com.evil.com`.  Invalid or empty regexes match nothing.  The compiled regexes are
+    /// cached for the first engine they are compiled with, and compiled afresh for any other
+    ///
+    /// # Arguments
+    ///
+    /// * `domain` - The domain of the cookie
+    /// * `name` - The name of the cookie
+    #[cfg(feature="rewriter")]
+    pub(crate) fn is_match<R: RegEx + Send + Sync + 'static>(&self, domain: &str, name: &str) -> bool {
+        let matches = |compiled: &Option<(R, R)>| match compiled {
+            Some((host, name_regex)) => host.is_match(domain) && name_regex.is_match(name),
+            None => false
+        };
+
+        let compiled = self.compiled.get_or_init(|| Arc::new(Self::compile::<R>(&self.host_regex, &self.name_regex)));
+        match compiled.downcast_ref::<Option<(R, R)>>() {
+            Some(compiled) => matches(compiled),
+            None => matches(&Self::compile::<R>(&self.host_regex, &self.name_regex))
         }
     }
 }
 
+impl fmt::Debug for CookieRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CookieRule")
+            .field("host_regex", &self.host_regex)
+            .field("name_regex", &self.name_regex)
+            .finish()
+    }
+}
+
+impl PartialEq for CookieRule {
+    fn eq(&self, other: &CookieRule) -> bool {
+        self.host_regex == other.host_regex && self.name_regex == other.name_regex
+    }
+}
+
+impl Eq for CookieRule {}
+
+impl Hash for CookieRule {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.host_regex.hash(state);
+        self.name_regex.hash(state);
+    }
+}
+
 
 /// A RuleSet is a grouping of rules which act on some target
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct RuleSet {
     pub name: String,
     pub rules: Vec<Rule>,
@@ -134,7 +381,10 @@ pub struct RuleSet {
     pub active: bool,
     pub default_state: bool,
     pub scope: Arc<Option<String>>, // RegExp
-    pub note: Option<String>
+    pub note: Option<String>,
+    pub source_channel: Arc<Option<String>>,
+    /// Rulesets of a higher priority are preferred over others which apply to the same host
+    pub priority: i32
 }
 
 impl RuleSet {
@@ -154,10 +404,17 @@ impl RuleSet {
             active: true,
             default_state: true,
             scope,
-            note: None
+            note: None,
+            source_channel: Arc::new(None),
+            priority: 0
         }
     }
 
+    /// Returns the name of the update channel this ruleset was added from, if any
+    pub fn source_channel(&self) -> Option<&str> {
+        self.source_channel.as_deref()
+    }
+
     #[cfg(feature="add_rulesets")]
     pub(crate) fn add_rules(&mut self, rules: &[Value]) {
         for rule in rules {
@@ -170,7 +427,16 @@ impl RuleSet {
                     Some(Value::String(to)) => to.to_string(),
                     _ => String::new(),
                 };
-                self.rules.push(Rule::new(from, to));
+                let to = Rule::normalize_backreferences(&from, &to);
+                if !Rule::has_valid_backreferences(&from, &to) {
+                    #[cfg(any(feature="rewriter",feature="updater"))]
+                    warn!("{}: skipping rule with a backreference to a missing group: {} -> {}", self.name, from, to);
+                    continue;
+                }
+                let rule = Rule::new(from, to);
+                if !self.rules.contains(&rule) {
+                    self.rules.push(rule);
+                }
             }
         }
     }
@@ -201,6 +467,11 @@ impl RuleSet {
                     Some(Value::String(name)) => name.to_string(),
                     _ => String::new(),
                 };
+                if host.is_empty() || name.is_empty() {
+                    #[cfg(any(feature="rewriter",feature="updater"))]
+                    warn!("{}: skipping securecookie with a missing host or name", self.name);
+                    continue;
+                }
 
                 cookierules_vec.push(
                     CookieRule::new(
@@ -212,16 +483,60 @@ impl RuleSet {
         self.cookierules = Some(cookierules_vec);
     }
 
+    /// Returns whether the given URL is covered by the exclusions of this ruleset, regardless of
+    /// whether any rule would otherwise rewrite it.  If the exclusions can't be compiled, every
+    /// URL is treated as excluded, since the ruleset is then not applied at all
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL to match against the exclusions
+    #[cfg(feature="rewriter")]
+    pub fn is_excluded(&self, url: &str) -> bool {
+        self.is_excluded_with::<Regex>(url)
+    }
+
+    /// Returns whether the given URL is covered by the exclusions of this ruleset, matching with
+    /// the regex engine `R`
     #[cfg(feature="rewriter")]
-    pub(crate) fn apply(&self, url: &str) -> Option<String> {
-        // If we're covered by an exclusion, return
-        if self.exclusions.is_some() {
-            let exclusions_regex = Regex::new(&self.exclusions.clone().unwrap()).unwrap();
-            if exclusions_regex.is_match(&url) {
+    fn is_excluded_with<R: RegEx>(&self, url: &str) -> bool {
+        if let Some(exclusions) = &self.exclusions {
+            let exclusions_regex = match R::try_new(exclusions) {
+                Some(exclusions_regex) => exclusions_regex,
+                None => {
+                    warn!("{}: skipping ruleset with invalid or oversized exclusions: {}", self.name, exclusions);
+                    return true;
+                }
+            };
+            if exclusions_regex.is_match(url) {
                debug!("Excluded url: {}", url);
-               return None;
+               return true;
             }
         }
+        false
+    }
+
+    /// Apply the rules of this ruleset to the given URL, matching with the regex engine `R`.
+    /// Returns the rewritten URL, or `None` if the URL is excluded or no rule rewrites it.
+    /// Exclusions are matched against the whole URL, but each rule is first applied only to the
+    /// part before any query or fragment, which are then re-attached unchanged, so that a loosely
+    /// written rule can't rewrite data carried in the query.  A rule which does not match that
+    /// part, for instance because it matches into the query, or which adds a query or fragment of
+    /// its own, is applied to the whole URL instead.  Patterns which the engine refuses to compile,
+    /// for instance because they exceed its size limits, are skipped with a warning; if the
+    /// exclusions can't be compiled, the ruleset is not applied at all
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL to rewrite
+    #[cfg(feature="rewriter")]
+    pub(crate) fn apply<R: RegEx>(&self, url: &str) -> Option<String> {
+        // If we're covered by an exclusion, return
+        if self.is_excluded_with::<R>(url) {
+            return None;
+        }
+
+        let is_query_or_fragment = |c: char| c == '?' || c == '#';
+        let base = &url[..url.find(is_query_or_fragment).unwrap_or(url.len())];
 
         for rule in self.rules.iter() {
             match rule {
@@ -233,8 +548,18 @@ impl RuleSet {
                     return Some(url);
                 }
                 Rule::NonTrivial(from_regex, to) => {
-                    let from_regex = Regex::new(from_regex).unwrap();
-                    let returl = from_regex.replace_all(url, &to[..]).to_string();
+                    let from_regex = match R::try_new(from_regex) {
+                        Some(from_regex) => from_regex,
+                        None => {
+                            warn!("{}: skipping invalid or oversized rule: {}", self.name, from_regex);
+                            continue;
+                        }
+                    };
+                    let returl = from_regex.replace_all(base, &to[..]);
+                    if returl != base && !returl.contains(is_query_or_fragment) {
+                        return Some(returl + &url[base.len()..]);
+                    }
+                    let returl = from_regex.replace_all(url, &to[..]);
                     if returl != url {
                         return Some(returl);
                     }
@@ -246,16 +571,80 @@ impl RuleSet {
 }
 
 
-/// RuleSets consists of a tuple btreemap of rulesets, keyed by some target FQDN
-#[derive(Debug)]
+/// How a host matched a target of a ruleset, returned by
+/// `RuleSets::potentially_applicable_detailed`.  Each variant holds the candidate target which
+/// matched
+#[cfg(feature="potentially_applicable")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MatchKind {
+    /// The host itself is a target, as `www.example.com`
+    Exact(String),
+    /// The host with its right-most label replaced by a wildcard, as `www.example.*`
+    RightWildcard(String),
+    /// A suffix of the host preceded by a single wildcard, as `*.example.com`
+    LeftWildcard(String),
+    /// A suffix of the host with every preceding label replaced by a wildcard, as `*.*.com`
+    LeftWildcards(String),
+}
+
+#[cfg(feature="potentially_applicable")]
+impl MatchKind {
+    /// Returns the match of the given candidate form for a host
+    fn new(host: &str, candidate: Candidate) -> MatchKind {
+        let labels: Vec<&str> = host.split('.').collect();
+        let suffix = |count: usize| labels[labels.len() - count..].join(".");
+        match candidate {
+            Candidate::Exact => MatchKind::Exact(host.to_string()),
+            Candidate::RightWildcard => MatchKind::RightWildcard(match host.rfind('.') {
+                Some(index) => format!("{}.*", &host[..index]),
+                None => String::from("*"),
+            }),
+            Candidate::LeftWildcard { labels: count } => MatchKind::LeftWildcard(format!("*.{}", suffix(count))),
+            Candidate::LeftWildcards { labels: count } => MatchKind::LeftWildcards(format!("{}{}", "*.".repeat(labels.len() - count), suffix(count))),
+        }
+    }
+
+    /// Returns the candidate target which matched
+    pub fn target(&self) -> &str {
+        match self {
+            MatchKind::Exact(target) |
+            MatchKind::RightWildcard(target) |
+            MatchKind::LeftWildcard(target) |
+            MatchKind::LeftWildcards(target) => target,
+        }
+    }
+}
+
+/// RuleSets consists of a tuple btreemap of rulesets, keyed by some target FQDN, along with an
+/// index of the targets for `potentially_applicable`
 #[derive(Default)]
-pub struct RuleSets(pub BTreeMap<String, Vec<Arc<RuleSet>>>);
+#[derive(Clone)]
+pub struct RuleSets(BTreeMap<String, Vec<Arc<RuleSet>>>, #[cfg(feature="potentially_applicable")] TargetIndex);
+
+// The target index is derived from the btreemap, so it is left out
+impl fmt::Debug for RuleSets {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("RuleSets").field(&self.0).finish()
+    }
+}
 
 impl RuleSets {
 
     /// Returns a new rulesets struct
     pub fn new() -> RuleSets {
-        RuleSets(BTreeMap::new())
+        RuleSets::default()
+    }
+
+    /// Add a ruleset under the given target
+    fn insert(&mut self, target: &str, ruleset: Arc<RuleSet>) {
+        #[cfg(feature="potentially_applicable")]
+        self.1.insert(target, Arc::clone(&ruleset));
+        match self.0.get_mut(target) {
+            Some(rs_vec) => rs_vec.push(ruleset),
+            None => {
+                self.0.insert(target.to_string(), vec![ruleset]);
+            }
+        }
     }
 
     /// Returns the number of targets in the current RuleSets struct as a `usize`
@@ -263,9 +652,70 @@ impl RuleSets {
         self.0.len()
     }
 
+    /// Returns the number of distinct rulesets in the current RuleSets struct as a `usize`.  A
+    /// ruleset stored under several targets is counted once
+    pub fn count_rulesets(&self) -> usize {
+        self.distinct_rulesets().len()
+    }
+
+    /// Returns the total number of rules across all distinct rulesets in the current RuleSets
+    /// struct as a `usize`
+    pub fn count_rules(&self) -> usize {
+        self.distinct_rulesets().iter().map(|ruleset| ruleset.rules.len()).sum()
+    }
+
+    fn distinct_rulesets(&self) -> Vec<&Arc<RuleSet>> {
+        let mut seen = HashSet::new();
+        self.0.values().flatten().filter(|ruleset| seen.insert(Arc::as_ptr(ruleset))).collect()
+    }
+
+    /// Returns whether the current RuleSets struct contains no targets
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns an iterator over all `(target, rulesets)` pairs in the current RuleSets struct, in
+    /// sorted order of target
+    pub fn iter(&self) -> impl Iterator<Item=(&str, &Vec<Arc<RuleSet>>)> {
+        self.0.iter().map(|(target, rulesets)| (target.as_str(), rulesets))
+    }
+
+    /// Returns an iterator over all targets in the current RuleSets struct, in sorted order
+    pub fn targets(&self) -> impl Iterator<Item=&str> {
+        self.0.keys().map(|target| target.as_str())
+    }
+
+    /// Returns the rulesets stored for the exact target given, if any.  Unlike
+    /// `potentially_applicable`, this performs no wildcard expansion
+    ///
+    /// # Arguments
+    ///
+    /// * `target` - The target to look up, e.g. `*.example.com`
+    pub fn rulesets_for_target(&self, target: &str) -> Option<&Vec<Arc<RuleSet>>> {
+        self.0.get(target)
+    }
+
     /// Clears the ruleset btreemap of all values
     pub fn clear(&mut self) {
         self.0.clear();
+        #[cfg(feature="potentially_applicable")]
+        self.1.clear();
+    }
+
+    /// Returns a compact binary encoding of the parsed rulesets, which can be stored and later
+    /// passed to `RuleSets::restore` to skip JSON parsing on startup
+    pub fn snapshot(&self) -> Vec<u8> {
+        snapshot::write(self)
+    }
+
+    /// Returns rulesets restored from a binary encoding produced by `RuleSets::snapshot`, or
+    /// `None` if the bytes are not a valid snapshot
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The snapshot to restore from
+    pub fn restore(bytes: &[u8]) -> Option<RuleSets> {
+        snapshot::read(bytes)
     }
 
     /// Construct and add new rulesets given a json string of values
@@ -279,104 +729,181 @@ impl RuleSets {
     /// or enabled
     /// * `scope` - An optional string which indicates the scope of the current batch of rulesets
     /// being added (see the [ruleset update channels](https://github.com/EFForg/https-everywhere/blob/master/docs/en_US/ruleset-update-channels.md) documentation)
+    ///
+    /// Panics if the json string is not valid; see `try_add_all_from_json_string` for a variant
+    /// which returns the error instead
     #[cfg(feature="add_rulesets")]
     pub fn add_all_from_json_string(&mut self, json_string: &str, enable_mixed_rulesets: bool, ruleset_active_states: &HashMap<String, bool>, scope: &Option<String>) {
-        let rulesets: Value = serde_json::from_str(&json_string).expect(ERROR_SERDE_PARSE);
-        self.add_all_from_serde_value(rulesets, enable_mixed_rulesets, ruleset_active_states, scope);
+        self.try_add_all_from_json_string(json_string, enable_mixed_rulesets, ruleset_active_states, scope)
+            .expect(ERROR_SERDE_PARSE);
+    }
+
+    /// Construct and add new rulesets given a json string of values, returning an error if the
+    /// json string is not valid.  Rulesets are added as they are parsed, so those preceding the
+    /// error are kept
+    ///
+    /// See `add_all_from_json_string` for a description of the arguments
+    #[cfg(feature="add_rulesets")]
+    pub fn try_add_all_from_json_string(&mut self, json_string: &str, enable_mixed_rulesets: bool, ruleset_active_states: &HashMap<String, bool>, scope: &Option<String>) -> Result<(), serde_json::Error> {
+        let mut deserializer = serde_json::Deserializer::from_str(json_string);
+        self.add_all_from_deserializer(&mut deserializer, enable_mixed_rulesets, ruleset_active_states, scope)?;
+        deserializer.end()
+    }
+
+    /// Construct and add new rulesets given a reader of json.  Rulesets are parsed and added one at
+    /// a time, so the whole json document is never held in memory.  Returns an error if the
+    /// stream is not valid json
+    ///
+    /// See `add_all_from_json_string` for a description of the remaining arguments
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - A reader of the json representing the rulesets to add
+    #[cfg(feature="add_rulesets")]
+    pub fn add_all_from_json_reader(&mut self, reader: impl Read, enable_mixed_rulesets: bool, ruleset_active_states: &HashMap<String, bool>, scope: &Option<String>) -> Result<(), serde_json::Error> {
+        let mut deserializer = serde_json::Deserializer::from_reader(BufReader::new(reader));
+        self.add_all_from_deserializer(&mut deserializer, enable_mixed_rulesets, ruleset_active_states, scope)?;
+        deserializer.end()
+    }
+
+    #[cfg(feature="add_rulesets")]
+    fn add_all_from_deserializer<'de, D: Deserializer<'de>>(&mut self, deserializer: D, enable_mixed_rulesets: bool, ruleset_active_states: &HashMap<String, bool>, scope: &Option<String>) -> Result<(), D::Error> {
+        RuleSetsSeed {
+            rulesets: self,
+            enable_mixed_rulesets,
+            ruleset_active_states,
+            scope: Arc::new(scope.clone()),
+            source_channel: Arc::new(None),
+        }.deserialize(deserializer)
+    }
+
+    /// Construct and add new rulesets given a reader of gzip-compressed json, decompressing and
+    /// parsing in a single pass.  Returns an error if the stream can not be decompressed or is not
+    /// valid json
+    ///
+    /// See `add_all_from_json_string` for a description of the remaining arguments
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - A reader of the gzip-compressed json representing the rulesets to add
+    #[cfg(feature="add_rulesets_gzip")]
+    pub fn add_all_from_gzip(&mut self, reader: impl Read, enable_mixed_rulesets: bool, ruleset_active_states: &HashMap<String, bool>, scope: &Option<String>) -> Result<(), serde_json::Error> {
+        self.add_all_from_json_reader(GzDecoder::new(reader), enable_mixed_rulesets, ruleset_active_states, scope)
     }
 
     #[cfg(feature="add_rulesets")]
     pub fn add_all_from_serde_value(&mut self, rulesets: Value, enable_mixed_rulesets: bool, ruleset_active_states: &HashMap<String, bool>, scope: &Option<String>) {
+        self.add_all_from_serde_value_with_source(rulesets, enable_mixed_rulesets, ruleset_active_states, scope, &None, 0);
+    }
+
+    /// Construct and add new rulesets given a serde_json::Value, recording the update channel
+    /// they came from and its priority on each ruleset
+    ///
+    /// See `add_all_from_json_string` for a description of the remaining arguments
+    ///
+    /// # Arguments
+    ///
+    /// * `source_channel` - The name of the update channel the rulesets were delivered by, if any
+    /// * `priority` - The priority of the rulesets over others which apply to the same host.  The
+    ///   rulesets added by other methods have a priority of 0
+    #[cfg(feature="add_rulesets")]
+    pub fn add_all_from_serde_value_with_source(&mut self, rulesets: Value, enable_mixed_rulesets: bool, ruleset_active_states: &HashMap<String, bool>, scope: &Option<String>, source_channel: &Option<String>, priority: i32) {
         let scope: Arc<Option<String>> = Arc::new(scope.clone());
+        let source_channel: Arc<Option<String>> = Arc::new(source_channel.clone());
 
-        let mut add_one_from_json = |ruleset: Value| {
-            if let Value::Object(ruleset) = ruleset {
-                let ruleset_name: String;
-                let mut default_state = true;
-                let mut note = String::new();
+        if let Value::Array(rulesets) = rulesets {
+            for ruleset in rulesets {
+                self.add_one_from_serde_value(ruleset, enable_mixed_rulesets, ruleset_active_states, &scope, &source_channel, priority);
+            }
+        }
+    }
 
-                if let Some(Value::String(default_off)) = ruleset.get(JSON_STRINGS.default_off) {
-                    if default_off != JSON_STRINGS.user_rule {
-                        default_state = false;
-                    }
-                    note.push_str(default_off);
-                    note.push_str("\n");
+    /// Construct and add a single ruleset given a serde_json::Value of one element of the rulesets
+    /// array
+    #[cfg(feature="add_rulesets")]
+    fn add_one_from_serde_value(&mut self, ruleset: Value, enable_mixed_rulesets: bool, ruleset_active_states: &HashMap<String, bool>, scope: &Arc<Option<String>>, source_channel: &Arc<Option<String>>, priority: i32) {
+        if let Value::Object(ruleset) = ruleset {
+            let ruleset_name: String;
+            let mut default_state = true;
+            let mut note = String::new();
+
+            if let Some(Value::String(default_off)) = ruleset.get(JSON_STRINGS.default_off) {
+                if default_off != JSON_STRINGS.user_rule {
+                    default_state = false;
                 }
+                note.push_str(default_off);
+                note.push_str("\n");
+            }
 
-                if let Some(Value::String(platform)) = ruleset.get(JSON_STRINGS.platform) {
-                    if platform == JSON_STRINGS.mixed_content {
-                        if !enable_mixed_rulesets {
-                            default_state = false;
-                        }
-                    } else {
+            if let Some(Value::String(platform)) = ruleset.get(JSON_STRINGS.platform) {
+                if platform == JSON_STRINGS.mixed_content {
+                    if !enable_mixed_rulesets {
                         default_state = false;
                     }
-
-                    note.push_str("Platform(s): ");
-                    note.push_str(platform);
-                    note.push_str("\n");
+                } else {
+                    default_state = false;
                 }
 
-                let mut active = default_state;
-                if let Some(Value::String(name)) = ruleset.get(JSON_STRINGS.name) {
-                    ruleset_name = name.to_string();
+                note.push_str("Platform(s): ");
+                note.push_str(platform);
+                note.push_str("\n");
+            }
 
-                    match ruleset_active_states.get(&ruleset_name) {
-                        Some(false) => { active = false; }
-                        Some(true) => { active = true; }
-                        _ => {}
-                    }
+            let mut active = default_state;
+            if let Some(Value::String(name)) = ruleset.get(JSON_STRINGS.name) {
+                ruleset_name = name.to_string();
 
-                    let mut rs = RuleSet::new(ruleset_name, Arc::clone(&scope));
-                    rs.default_state = default_state;
-                    rs.note = match note.len() {
-                        0 => None,
-                        _ => Some(note.trim().to_string())
-                    };
+                match ruleset_active_states.get(&ruleset_name) {
+                    Some(false) => { active = false; }
+                    Some(true) => { active = true; }
+                    _ => {}
+                }
+
+                let mut rs = RuleSet::new(ruleset_name, Arc::clone(scope));
+                rs.source_channel = Arc::clone(source_channel);
+                rs.priority = priority;
+                rs.default_state = default_state;
+                rs.note = match note.len() {
+                    0 => None,
+                    _ => Some(note.trim().to_string())
+                };
 
-                    rs.active = active;
+                rs.active = active;
 
-                    if let Some(Value::Array(rules)) = ruleset.get(JSON_STRINGS.rule) {
-                        rs.add_rules(rules);
-                    }
+                if let Some(Value::Array(rules)) = ruleset.get(JSON_STRINGS.rule) {
+                    rs.add_rules(rules);
+                }
 
-                    if let Some(Value::Array(exclusions)) = ruleset.get(JSON_STRINGS.exclusion) {
-                        rs.add_exclusions(exclusions);
-                    }
+                if let Some(Value::Array(exclusions)) = ruleset.get(JSON_STRINGS.exclusion) {
+                    rs.add_exclusions(exclusions);
+                }
 
-                    if let Some(Value::Array(securecookies)) = ruleset.get(JSON_STRINGS.securecookie) {
-                        rs.add_cookierules(securecookies);
-                    }
+                if let Some(Value::Array(securecookies)) = ruleset.get(JSON_STRINGS.securecookie) {
+                    rs.add_cookierules(securecookies);
+                }
 
-                    let rs_rc = Arc::new(rs);
-                    if let Some(Value::Array(targets)) = ruleset.get(JSON_STRINGS.target) {
-                        for target in targets {
-                            if let Value::String(target) = target {
-                                match self.0.get_mut(target) {
-                                    Some(rs_vec) => {
-                                        rs_vec.push(Arc::clone(&rs_rc));
-                                    },
-                                    None => {
-                                        self.0.insert(target.to_string(), vec![Arc::clone(&rs_rc)]);
-                                    }
-                                }
-                            }
+                let rs_rc = Arc::new(rs);
+                if let Some(Value::Array(targets)) = ruleset.get(JSON_STRINGS.target) {
+                    for target in targets {
+                        if let Value::String(target) = target {
+                            self.insert(target, Arc::clone(&rs_rc));
                         }
                     }
                 }
             }
-        };
-
-        if let Value::Array(rulesets) = rulesets {
-            for ruleset in rulesets {
-                add_one_from_json(ruleset);
-            }
         }
     }
 
-    /// Return a vector of `(host, ruleset, rule)`s which are active and have no exclusions, for
-    /// all hosts that are in a single ruleset, end in the given ending, and match the rule `from`
-    /// regex.  Provide the regex engine that implements the trait `RegEx` as a generic.
+    /// Return a vector of `(host, ruleset, rule)`s for the simple rules of hosts which end in the
+    /// given ending.  A rule is simple for a host if:
+    ///
+    /// 1. the host is a target of exactly one ruleset, which is active and has no exclusions
+    /// 2. the rule `from` regex matches `http://{host}/`
+    /// 3. the rule rewrites to `https:`, as `Rule::Trivial` does
+    ///
+    /// Such hosts can be upgraded without consulting the rulesets for the rest of the URL.
+    /// Provide the regex engine that implements the trait `RegEx` as a generic; with the
+    /// `rewriter` feature, `regex::Regex` implements it.
     ///
     /// # Arguments
     ///
@@ -390,7 +917,13 @@ impl RuleSets {
                ruleset[0].active &&
                ruleset[0].exclusions.is_none() {
                 for rule in &ruleset[0].rules {
-                    let from_re = T::new(&rule.from_regex());
+                    if !rule.to().starts_with("https:") {
+                        continue;
+                    }
+                    let from_re = match T::try_new(&rule.from_regex()) {
+                        Some(from_re) => from_re,
+                        None => continue
+                    };
                     if from_re.is_match(&format!("http://{}/", host)) {
                         results.push((host, Arc::clone(&ruleset[0]), rule));
                     }
@@ -400,7 +933,18 @@ impl RuleSets {
         results
     }
 
-    /// Return a vector of rulesets that apply to the given host
+    /// Return a vector of rulesets that apply to the given host.  For a host such as
+    /// `a.b.example.com`, rulesets are looked up for the following candidate targets, in order:
+    ///
+    /// 1. the host itself: `a.b.example.com`
+    /// 2. the host with its right-most label replaced: `a.b.example.*`
+    /// 3. each suffix with a single left wildcard: `*.b.example.com`, `*.example.com`, `*.com`
+    /// 4. each suffix with every preceding label replaced by a wildcard, where more than one label
+    ///    precedes it: `*.*.example.com`, `*.*.*.com`
+    ///
+    /// Rather than building each candidate target, the candidates are found with a single walk of
+    /// an index of the targets by label.  The rulesets are then ordered by descending priority,
+    /// rulesets of the same priority remaining in the order above
     ///
     /// # Arguments
     ///
@@ -408,51 +952,96 @@ impl RuleSets {
     #[cfg(feature="potentially_applicable")]
     pub fn potentially_applicable(&self, host: &str) -> Vec<Arc<RuleSet>> {
         let mut results = vec![];
-
-        self.try_add(&mut results, host);
-
-        // Ensure host is well-formed (RFC 1035)
-        if host.is_empty() || host.len() > 255 || host.find("..").is_some() {
-            return results;
+        if let Some(host) = Self::well_formed_host(host) {
+            self.1.lookup(host, &mut results);
+            results.sort_by_key(|ruleset| Reverse(ruleset.priority));
         }
+        results
+    }
 
-        // Replace www.example.com with www.example.*
-        // eat away from the right for once and only once
-        let mut segmented: Vec<&str> = host.split('.').collect();
-        let last_index = segmented.len() - 1;
-        let tld = segmented[last_index];
-
-        segmented[last_index] = "*";
-        let tmp_host = segmented.join(".");
-        self.try_add(&mut results, &tmp_host);
-        segmented[last_index] = tld;
-
-        // now eat away from the left, with *, so that for x.y.z.google.com we
-        // check *.y.z.google.com, *.z.google.com and *.google.com
-        for index in 0..(segmented.len() - 1) {
-            let mut segmented_tmp = segmented.clone();
-            segmented_tmp[index] = "*";
-            if let Some(slice) = segmented_tmp.get(index..segmented.len()) {
-                let tmp_host = slice.join(".");
-                self.try_add(&mut results, &tmp_host);
-            }
+    /// Return a vector of rulesets that apply to the given host, as `potentially_applicable`
+    /// does, along with how the host matched a target of each ruleset.  This is meant for
+    /// diagnosing why a ruleset applies; use `potentially_applicable` to rewrite URLs
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - A string which indicates the host to search for potentially applicable rulesets
+    #[cfg(feature="potentially_applicable")]
+    pub fn potentially_applicable_detailed(&self, host: &str) -> Vec<(Arc<RuleSet>, MatchKind)> {
+        let mut results = vec![];
+        if let Some(host) = Self::well_formed_host(host) {
+            self.1.lookup_with(host, |rulesets, candidate| {
+                let match_kind = MatchKind::new(host, candidate);
+                results.extend(rulesets.iter().map(|ruleset| (Arc::clone(ruleset), match_kind.clone())));
+            });
+            results.sort_by_key(|(ruleset, _)| Reverse(ruleset.priority));
         }
-
         results
     }
 
+    /// Returns the host without any trailing dot, so that fully-qualified hosts (example.com.)
+    /// are treated the same as their unqualified form, or `None` if it is not well-formed
+    /// (RFC 1035)
     #[cfg(feature="potentially_applicable")]
-    fn try_add(&self, results: &mut Vec<Arc<RuleSet>>, host: &str) {
-        if self.0.contains_key(host) {
-            if let Some(rulesets) = self.0.get(host) {
-                for ruleset in rulesets {
-                    results.push(Arc::clone(ruleset));
-                }
-            }
+    fn well_formed_host(host: &str) -> Option<&str> {
+        let host = host.strip_suffix('.').unwrap_or(host);
+        if host.is_empty() || host.len() > 255 || host.split('.').any(|label| label.is_empty()) {
+            None
+        } else {
+            Some(host)
         }
     }
 }
 
+/// Streams the top-level rulesets array, constructing and adding each ruleset as soon as it has
+/// been parsed so that the `Value` tree of the whole array is never built
+#[cfg(feature="add_rulesets")]
+struct RuleSetsSeed<'a> {
+    rulesets: &'a mut RuleSets,
+    enable_mixed_rulesets: bool,
+    ruleset_active_states: &'a HashMap<String, bool>,
+    scope: Arc<Option<String>>,
+    source_channel: Arc<Option<String>>,
+}
+
+#[cfg(feature="add_rulesets")]
+impl<'de, 'a> DeserializeSeed<'de> for RuleSetsSeed<'a> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+// As with `add_all_from_serde_value`, json other than an array is valid but adds no rulesets
+#[cfg(feature="add_rulesets")]
+impl<'de, 'a> Visitor<'de> for RuleSetsSeed<'a> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of rulesets")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(ruleset) = seq.next_element::<Value>()? {
+            self.rulesets.add_one_from_serde_value(ruleset, self.enable_mixed_rulesets, self.ruleset_active_states, &self.scope, &self.source_channel, 0);
+        }
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while map.next_entry::<IgnoredAny, IgnoredAny>()?.is_some() {}
+        Ok(())
+    }
+
+    fn visit_bool<E: de::Error>(self, _value: bool) -> Result<(), E> { Ok(()) }
+    fn visit_i64<E: de::Error>(self, _value: i64) -> Result<(), E> { Ok(()) }
+    fn visit_u64<E: de::Error>(self, _value: u64) -> Result<(), E> { Ok(()) }
+    fn visit_f64<E: de::Error>(self, _value: f64) -> Result<(), E> { Ok(()) }
+    fn visit_str<E: de::Error>(self, _value: &str) -> Result<(), E> { Ok(()) }
+    fn visit_unit<E: de::Error>(self) -> Result<(), E> { Ok(()) }
+}
+
 #[cfg(all(test,feature="add_rulesets"))]
 pub mod tests {
     use super::*;
@@ -473,6 +1062,258 @@ pub mod tests {
         assert_eq!(rs.count_targets(), 28);
     }
 
+    #[test]
+    #[cfg(feature="rewriter")]
+    fn cookierules_match_in_full() {
+        let cookierule = CookieRule::new(String::from("example\\.com"), String::from("session"));
+        assert!(cookierule.is_match::<Regex>("example.com", "session"));
+        assert!(!cookierule.is_match::<Regex>("notexample.com.evil.com", "session"));
+        assert!(!cookierule.is_match::<Regex>("example.com", "session_id"));
+
+        let cookierule = CookieRule::new(String::from("^.+\\.example\\.com$"), String::from(".+"));
+        assert!(cookierule.is_match::<Regex>("www.example.com", "session"));
+        assert!(!cookierule.is_match::<Regex>("example.com", "session"));
+    }
+
+    #[test]
+    #[cfg(feature="rewriter")]
+    fn skips_cookierules_missing_host_or_name() {
+        let mut rs = RuleSets::new();
+        rs.add_all_from_json_string(r#"[{"name":"Cookies","target":["example.com"],"rule":[{"from":"^http:","to":"https:"}],"securecookie":[
+            {"host":".+"},
+            {"host":"","name":".+"},
+            {"host":"^example\\.com$","name":"^session$"}
+        ]}]"#, ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None);
+
+        let cookierules = rs.rulesets_for_target("example.com").unwrap()[0].cookierules.clone().unwrap();
+        assert_eq!(cookierules.len(), 1);
+        assert!(cookierules[0].is_match::<Regex>("example.com", "session"));
+        assert!(!cookierules[0].is_match::<Regex>("example.com", "tracking"));
+
+        let cookierule = CookieRule::new(String::from(".+"), String::new());
+        assert!(!cookierule.is_match::<Regex>("example.com", "session"));
+        assert!(!cookierule.is_match::<Regex>("example.com", ""));
+    }
+
+    #[test]
+    #[cfg(feature="rewriter")]
+    fn skips_oversized_patterns() {
+        let mut ruleset = RuleSet::new(String::from("Oversized"), Arc::new(None));
+        ruleset.rules = vec![
+            Rule::new(String::from("^http://\\w{1000}\\.example\\.com/"), String::from("https://example.com/")),
+            Rule::new(String::from("^http://www\\.example\\.com/"), String::from("https://example.com/")),
+        ];
+        assert_eq!(ruleset.apply::<Regex>("http://www.example.com/"), Some(String::from("https://example.com/")));
+
+        ruleset.exclusions = Some(String::from("\\w{1000}"));
+        assert_eq!(ruleset.apply::<Regex>("http://www.example.com/"), None);
+    }
+
+    #[test]
+    #[cfg(feature="rewriter")]
+    fn tests_single_rule() {
+        assert_eq!(Rule::test("^http:", "https:", "http://example.com/"), Ok(Some(String::from("https://example.com/"))));
+        assert_eq!(
+            Rule::test("^http://(www\\.)?example\\.com/", "https://www.example.com/", "http://example.com/?q=1"),
+            Ok(Some(String::from("https://www.example.com/?q=1"))));
+        assert_eq!(Rule::test("^http://www\\.example\\.com/", "https://www.example.com/", "http://example.org/"), Ok(None));
+        assert!(Rule::test("^http://(unclosed", "https://", "http://example.com/").is_err());
+    }
+
+    #[test]
+    #[cfg(feature="rewriter")]
+    fn tests_exclusions() {
+        let mut rs = RuleSets::new();
+        add_mock_rulesets(&mut rs);
+        let ruleset = &rs.0.get("gstatic.com").unwrap()[0];
+
+        assert!(ruleset.is_excluded("http://www.gstatic.com/generate_204"));
+        assert!(ruleset.is_excluded("http://ajax.googleapis.com/"));
+        assert!(!ruleset.is_excluded("http://www.gstatic.com/"));
+        assert!(!ruleset.is_excluded("http://ajax.googleapis.com/ajax/libs/"));
+
+        let ruleset = &rs.0.get("freerangekitten.com").unwrap()[0];
+        assert!(!ruleset.is_excluded("http://freerangekitten.com/"));
+    }
+
+    #[test]
+    #[cfg(feature="rewriter")]
+    fn preserves_query_and_fragment() {
+        let mut ruleset = RuleSet::new(String::from("Example"), Arc::new(None));
+        ruleset.rules = vec![
+            Rule::new(String::from("^http://(www\\.)?example\\.com/"), String::from("https://www.example.com/")),
+            Rule::new(String::from("^http://added\\.example\\.com/$"), String::from("https://example.com/?source=added#top")),
+        ];
+
+        // A loosely written rule doesn't touch URLs in the query or fragment
+        assert_eq!(
+            ruleset.apply::<Regex>("http://example.com/login?next=http://example.com/#http://example.com/"),
+            Some(String::from("https://www.example.com/login?next=http://example.com/#http://example.com/")));
+        assert_eq!(
+            ruleset.apply::<Regex>("http://example.com/#"),
+            Some(String::from("https://www.example.com/#")));
+
+        // Rules which add a query or fragment, or which match into the query, apply to the whole
+        // URL
+        assert_eq!(
+            ruleset.apply::<Regex>("http://added.example.com/"),
+            Some(String::from("https://example.com/?source=added#top")));
+        assert_eq!(ruleset.apply::<Regex>("http://added.example.com/?page=2#section"), None);
+
+        ruleset.rules = vec![Rule::new(String::from("^http://example\\.com/search\\?q="), String::from("https://search.example.com/?q="))];
+        assert_eq!(
+            ruleset.apply::<Regex>("http://example.com/search?q=kittens#results"),
+            Some(String::from("https://search.example.com/?q=kittens#results")));
+        assert_eq!(ruleset.apply::<Regex>("http://example.com/search"), None);
+    }
+
+    #[test]
+    #[cfg(feature="add_rulesets_gzip")]
+    fn adds_from_gzip() {
+        use flate2::{Compression, write::GzEncoder};
+        use std::io::Write;
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(mock_rulesets_json().as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut rs = RuleSets::new();
+        rs.add_all_from_gzip(&compressed[..], ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None).unwrap();
+
+        let mut expected = RuleSets::new();
+        add_mock_rulesets(&mut expected);
+        assert_eq!(format!("{:?}", rs), format!("{:?}", expected));
+
+        assert!(RuleSets::new().add_all_from_gzip(&compressed[1..], ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None).is_err());
+    }
+
+    #[test]
+    fn streams_same_as_serde_value() {
+        let mut streamed = RuleSets::new();
+        streamed.add_all_from_json_reader(mock_rulesets_json().as_bytes(), ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None).unwrap();
+
+        let mut from_value = RuleSets::new();
+        let rulesets: Value = serde_json::from_str(&mock_rulesets_json()).unwrap();
+        from_value.add_all_from_serde_value(rulesets, ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None);
+
+        assert_eq!(format!("{:?}", streamed), format!("{:?}", from_value));
+
+        let mut not_array = RuleSets::new();
+        not_array.add_all_from_json_reader(&b"{\"rulesets\": []}"[..], ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None).unwrap();
+        assert!(not_array.is_empty());
+        assert!(RuleSets::new().add_all_from_json_reader(&b"[{}"[..], ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None).is_err());
+    }
+
+    #[test]
+    fn deduplicates_rules() {
+        let mut rs = RuleSets::new();
+        rs.add_all_from_json_string(r#"[{"name":"Dupes","target":["example.com"],"rule":[{"from":"^http:","to":"https:"},{"from":"^http://example\\.com/","to":"https://www.example.com/"},{"from":"^http:","to":"https:"}]}]"#, ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None);
+
+        let rules = &rs.rulesets_for_target("example.com").unwrap()[0].rules;
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0], Rule::Trivial);
+    }
+
+    #[test]
+    fn skips_rules_with_missing_backreferences() {
+        let mut rs = RuleSets::new();
+        rs.add_all_from_json_string(r#"[{"name":"Backreferences","target":["example.com","*.example.com"],"rule":[
+            {"from":"^http://(www\\.)?example\\.com/","to":"https://$2example.com/"},
+            {"from":"^http://(\\w+)\\.example\\.com/\\(","to":"https://${2}.example.com/"},
+            {"from":"^http://(\\w+)\\.example\\.com/","to":"https://${1a}.example.com/"},
+            {"from":"^http://(?P<sub>\\w+)\\.example\\.com/","to":"https://$subdomain.example.com/"},
+            {"from":"^http://(?P<sub>\\w+)\\.example\\.com/\\(([^)])","to":"https://$1a.example.com/$$3$2$0$sub"},
+            {"from":"^http://(www\\.)?example\\.com/","to":"https://${1}example.com/"}
+        ]}]"#, ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None);
+
+        let rules = &rs.rulesets_for_target("example.com").unwrap()[0].rules;
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].to(), "https://${1}a.example.com/$$3${2}${0}$sub");
+        assert_eq!(rules[1].to(), "https://${1}example.com/");
+    }
+
+    #[test]
+    fn normalizes_backreferences() {
+        assert_eq!(Rule::normalize_backreferences("^http://(a)?(b)?c/", "https://$1$2c/"), "https://${1}${2}c/");
+        assert_eq!(Rule::normalize_backreferences("^http://(a)(b)(c)(d)(e)(f)(g)(h)(i)(j)(k)/", "https://$11/$12/$1a/"), "https://${11}/${1}2/${1}a/");
+        assert_eq!(Rule::normalize_backreferences("^http://(a)/", "https://$$1/${1}/$/$"), "https://$$1/${1}/$/$");
+
+        let mut rs = RuleSets::new();
+        add_mock_rulesets(&mut rs);
+        let ruleset = rs.rulesets_for_target("commondatastorage.googleapis.com").unwrap().iter().find(|ruleset| ruleset.name == "Google APIs").unwrap();
+        assert_eq!(ruleset.apply::<Regex>("http://commondatastorage.googleapis.com/bucket"), Some(String::from("https://commondatastorage.googleapis.com/bucket")));
+        assert_eq!(ruleset.apply::<Regex>("http://a.storage.googleapis.com/bucket"), Some(String::from("https://a.storage.googleapis.com/bucket")));
+    }
+
+    #[test]
+    fn compares_rulesets() {
+        let mut rs = RuleSets::new();
+        add_mock_rulesets(&mut rs);
+        let mut rs2 = RuleSets::new();
+        add_mock_rulesets(&mut rs2);
+
+        let ruleset = &rs.rulesets_for_target("freerangekitten.com").unwrap()[0];
+        assert_eq!(ruleset, &rs2.rulesets_for_target("freerangekitten.com").unwrap()[0]);
+        assert_ne!(ruleset, &rs.rulesets_for_target("1fichier.com").unwrap()[0]);
+
+        let rules: std::collections::HashSet<&Rule> = rs.iter().flat_map(|(_, rulesets)| rulesets.iter().flat_map(|ruleset| &ruleset.rules)).collect();
+        assert!(rules.contains(&Rule::Trivial));
+    }
+
+    #[test]
+    fn clones_independently() {
+        let mut rs = RuleSets::new();
+        add_mock_rulesets(&mut rs);
+
+        let mut rs_clone = rs.clone();
+        assert_eq!(format!("{:?}", rs_clone), format!("{:?}", rs));
+        rs_clone.clear();
+        assert_eq!(rs.count_targets(), 28);
+
+        let original = &rs.rulesets_for_target("freerangekitten.com").unwrap()[0];
+        let mut ruleset = RuleSet::clone(original);
+        ruleset.active = false;
+        ruleset.rules.clear();
+        assert!(original.active);
+        assert_eq!(original.rules.len(), 1);
+    }
+
+    #[test]
+    fn fails_to_add_malformed_json() {
+        let mut rs = RuleSets::new();
+        let json = r#"[{"name": "Example", "target": ["example.com"], "rule": [{"from": "^http:", "to": "https:"}]}, {"name": "#;
+        assert!(rs.try_add_all_from_json_string(json, ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None).is_err());
+        assert_eq!(rs.count_targets(), 1);
+
+        assert!(rs.try_add_all_from_json_string("[]", ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None).is_ok());
+    }
+
+    #[test]
+    fn enumerates_targets() {
+        let mut rs = RuleSets::new();
+        add_mock_rulesets(&mut rs);
+
+        assert!(RuleSets::new().is_empty());
+        assert!(RuleSets::default().is_empty());
+        assert!(!rs.is_empty());
+        assert_eq!(rs.targets().count(), 28);
+        assert_eq!(rs.iter().count(), 28);
+        assert!(rs.targets().any(|target| target == "*.1fichier.com"));
+        assert_eq!(rs.rulesets_for_target("*.1fichier.com").unwrap()[0].name, "1fichier");
+        assert!(rs.rulesets_for_target("foo.1fichier.com").is_none());
+    }
+
+    #[test]
+    fn counts_rulesets_and_rules() {
+        let mut rs = RuleSets::new();
+        assert_eq!(rs.count_rulesets(), 0);
+        assert_eq!(rs.count_rules(), 0);
+
+        add_mock_rulesets(&mut rs);
+        assert_eq!(rs.count_rulesets(), 3);
+        assert_eq!(rs.count_rules(), 7);
+    }
+
     #[test]
     fn rulesets_represented_correctly() {
         let mut rs = RuleSets::new();
@@ -488,8 +1329,17 @@ pub mod tests {
         let mut rs = RuleSets::new();
         add_mock_rulesets(&mut rs);
 
-        assert_eq!(rs.get_simple_rules_ending_with::<Regex>(".com").len(), 2);
+        let mut hosts: Vec<&String> = rs.get_simple_rules_ending_with::<Regex>(".com").into_iter().map(|(host, _, rule)| {
+            assert_eq!(rule, &Rule::Trivial);
+            host
+        }).collect();
+        hosts.sort();
+        assert_eq!(hosts, vec!["freerangekitten.com", "www.freerangekitten.com"]);
         assert_eq!(rs.get_simple_rules_ending_with::<Regex>(".org").len(), 0);
+
+        // Rules which don't upgrade to https are not simple
+        rs.add_all_from_json_string(r#"[{"name": "Downgrade", "target": ["downgrade.com"], "rule": [{"from": "^http:", "to": "http:"}]}]"#, ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None);
+        assert_eq!(rs.get_simple_rules_ending_with::<Regex>(".com").len(), 2);
     }
 
     #[test]
@@ -513,6 +1363,113 @@ pub mod tests {
         assert_eq!(rs.potentially_applicable("bar.foo.storage.googleapis.com").len(), 1);
     }
 
+    #[test]
+    #[cfg(feature="potentially_applicable")]
+    fn potentially_applicable_deep_subdomains() {
+        let mut rs = RuleSets::new();
+        add_mock_rulesets(&mut rs);
+
+        assert_eq!(rs.potentially_applicable("a.b.c.1fichier.com").len(), 1);
+        assert_eq!(rs.potentially_applicable("a.b.c.d.storage.googleapis.com").len(), 1);
+    }
+
+    #[test]
+    #[cfg(feature="potentially_applicable")]
+    fn potentially_applicable_multi_label_wildcard() {
+        let mut rs = RuleSets::new();
+        rs.add_all_from_json_string(r#"[{"name":"Multi","target":["*.*.example.com"],"rule":[{"from":"^http:","to":"https:"}]}]"#, ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None);
+
+        assert_eq!(rs.potentially_applicable("example.com").len(), 0);
+        assert_eq!(rs.potentially_applicable("foo.example.com").len(), 0);
+        assert_eq!(rs.potentially_applicable("bar.foo.example.com").len(), 1);
+        assert_eq!(rs.potentially_applicable("baz.bar.foo.example.com").len(), 0);
+    }
+
+    #[test]
+    #[cfg(feature="potentially_applicable")]
+    fn potentially_applicable_detailed() {
+        let mut rs = RuleSets::new();
+        rs.add_all_from_json_string(r#"[
+            {"name": "Exact", "target": ["www.example.com"], "rule": [{"from": "^http:", "to": "https:"}]},
+            {"name": "Right", "target": ["www.example.*"], "rule": [{"from": "^http:", "to": "https:"}]},
+            {"name": "Left", "target": ["*.example.com"], "rule": [{"from": "^http:", "to": "https:"}]},
+            {"name": "Multi", "target": ["*.*.com"], "rule": [{"from": "^http:", "to": "https:"}]}
+        ]"#, ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None);
+
+        let detailed: Vec<(String, MatchKind)> = rs.potentially_applicable_detailed("www.example.com.").into_iter()
+            .map(|(ruleset, match_kind)| (ruleset.name.clone(), match_kind))
+            .collect();
+        assert_eq!(detailed, vec![
+            (String::from("Exact"), MatchKind::Exact(String::from("www.example.com"))),
+            (String::from("Right"), MatchKind::RightWildcard(String::from("www.example.*"))),
+            (String::from("Left"), MatchKind::LeftWildcard(String::from("*.example.com"))),
+            (String::from("Multi"), MatchKind::LeftWildcards(String::from("*.*.com"))),
+        ]);
+        assert_eq!(detailed[2].1.target(), "*.example.com");
+        assert!(rs.potentially_applicable_detailed("www..com").is_empty());
+    }
+
+    #[test]
+    #[cfg(feature="potentially_applicable")]
+    fn potentially_applicable_malformed_hosts() {
+        let mut rs = RuleSets::new();
+        add_mock_rulesets(&mut rs);
+
+        assert_eq!(rs.potentially_applicable(".1fichier.com").len(), 0);
+        assert_eq!(rs.potentially_applicable("1fichier..com").len(), 0);
+        assert_eq!(rs.potentially_applicable("1fichier.com.").len(), 1);
+        assert_eq!(rs.potentially_applicable("1fichier.com..").len(), 0);
+        assert_eq!(rs.potentially_applicable(".").len(), 0);
+    }
+
+    /// The candidate targets of a host, in the order documented by `potentially_applicable`
+    #[cfg(feature="potentially_applicable")]
+    fn candidate_targets(host: &str) -> Vec<String> {
+        let labels: Vec<&str> = host.split('.').collect();
+        let len = labels.len();
+        let mut candidates = vec![host.to_string()];
+
+        let mut tld_replaced = labels.clone();
+        tld_replaced[len - 1] = "*";
+        candidates.push(tld_replaced.join("."));
+
+        for index in 0..(len - 1) {
+            candidates.push(format!("*.{}", labels[index + 1..].join(".")));
+        }
+
+        for index in 2..len {
+            let mut wildcarded = labels.clone();
+            for label in wildcarded.iter_mut().take(index) {
+                *label = "*";
+            }
+            candidates.push(wildcarded.join("."));
+        }
+        candidates
+    }
+
+    #[test]
+    #[cfg(feature="potentially_applicable")]
+    fn potentially_applicable_matches_candidate_targets() {
+        let mut rs = RuleSets::new();
+        add_mock_rulesets(&mut rs);
+        rs.add_all_from_json_string(r#"[
+            {"name": "Exact", "target": ["a.b.example.com", "example.com", "com"], "rule": [{"from": "^http:", "to": "https:"}]},
+            {"name": "Right", "target": ["a.b.example.*", "example.*", "*"], "rule": [{"from": "^http:", "to": "https:"}]},
+            {"name": "Left", "target": ["*.b.example.com", "*.example.com", "*.com"], "rule": [{"from": "^http:", "to": "https:"}]},
+            {"name": "Multi", "target": ["*.*.example.com", "*.*.*.com", "*.*.com"], "rule": [{"from": "^http:", "to": "https:"}]},
+            {"name": "Middle", "target": ["a.*.example.com", "b.example.com"], "rule": [{"from": "^http:", "to": "https:"}]}
+        ]"#, ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None);
+
+        for host in ["a.b.example.com", "b.example.com", "example.com", "com", "x.y.z.example.com", "a.b.c.1fichier.com", "foo.storage.googleapis.com", "freerangekitten.com", "nonmatch.example.org"] {
+            let expected: Vec<Arc<RuleSet>> = candidate_targets(host).iter()
+                .filter_map(|target| rs.rulesets_for_target(target))
+                .flatten().cloned().collect();
+            let actual = rs.potentially_applicable(host);
+            assert_eq!(actual.len(), expected.len(), "{}", host);
+            assert!(actual.iter().zip(&expected).all(|(a, e)| Arc::ptr_eq(a, e)), "{}", host);
+        }
+    }
+
     #[test]
     #[cfg(feature="potentially_applicable")]
     fn potentially_applicable_no_matches() {
@@ -522,6 +1479,52 @@ pub mod tests {
         assert_eq!(rs.potentially_applicable("nonmatch.example.com").len(), 0);
     }
 
+    #[test]
+    fn restores_from_snapshot() {
+        let mut rs = RuleSets::new();
+        add_mock_rulesets(&mut rs);
+
+        let restored = RuleSets::restore(&rs.snapshot()).unwrap();
+        assert_eq!(restored.count_targets(), 28);
+        assert_eq!(format!("{:?}", restored), format!("{:?}", rs));
+        #[cfg(feature="potentially_applicable")]
+        assert_eq!(restored.potentially_applicable("foo.1fichier.com").len(), 1);
+
+        assert!(RuleSets::restore(&rs.snapshot()[1..]).is_none());
+    }
+
+    #[test]
+    fn records_source_channel() {
+        let mut rs = RuleSets::new();
+        add_mock_rulesets(&mut rs);
+        let rulesets: Value = serde_json::from_str(r#"[{"name": "A", "target": ["a.example.com"], "rule": [{"from": "^http:", "to": "https:"}]}]"#).unwrap();
+        rs.add_all_from_serde_value_with_source(rulesets, ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None, &Some(String::from("Test Channel")), 0);
+
+        assert_eq!(rs.rulesets_for_target("a.example.com").unwrap()[0].source_channel(), Some("Test Channel"));
+        assert_eq!(rs.rulesets_for_target("1fichier.com").unwrap()[0].source_channel(), None);
+
+        let restored = RuleSets::restore(&rs.snapshot()).unwrap();
+        assert_eq!(restored.rulesets_for_target("a.example.com").unwrap()[0].source_channel(), Some("Test Channel"));
+    }
+
+    #[test]
+    #[cfg(feature="potentially_applicable")]
+    fn orders_potentially_applicable_by_priority() {
+        let mut rs = RuleSets::new();
+        let ruleset = |name: &str, target: &str| serde_json::json!([{"name": name, "target": [target], "rule": [{"from": "^http:", "to": "https:"}]}]);
+        rs.add_all_from_serde_value_with_source(ruleset("Exact", "www.example.com"), ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None, &None, 0);
+        rs.add_all_from_serde_value_with_source(ruleset("Low", "*.example.com"), ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None, &None, -1);
+        rs.add_all_from_serde_value_with_source(ruleset("Wildcard", "*.example.com"), ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None, &None, 0);
+        rs.add_all_from_serde_value_with_source(ruleset("High", "*.example.com"), ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None, &None, 10);
+
+        let names: Vec<String> = rs.potentially_applicable("www.example.com").iter().map(|ruleset| ruleset.name.clone()).collect();
+        assert_eq!(names, vec!["High", "Exact", "Wildcard", "Low"]);
+
+        let restored = RuleSets::restore(&rs.snapshot()).unwrap();
+        let names: Vec<String> = restored.potentially_applicable("www.example.com").iter().map(|ruleset| ruleset.name.clone()).collect();
+        assert_eq!(names, vec!["High", "Exact", "Wildcard", "Low"]);
+    }
+
     #[test]
     fn is_threadsafe() {
         let mut rs = RuleSets::new();
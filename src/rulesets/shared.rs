@@ -0,0 +1,56 @@
+//! Access to rulesets shared between threads, independent of whether they are shared behind an
+//! `RwLock` or, with the `arc-swap` feature, an `ArcSwap`
+
+use std::ops::Deref;
+use std::sync::TryLockResult;
+
+use super::{RuleSets, ThreadSafeRuleSets};
+
+cfg_if::cfg_if! {
+    if #[cfg(feature="arc-swap")] {
+        /// Returns the rulesets wrapped to be shared between threads
+        #[cfg(any(feature="cffi", feature="wasm", all(test, any(feature="updater", feature="add_rulesets"))))]
+        pub(crate) fn new(rulesets: RuleSets) -> ThreadSafeRuleSets {
+            std::sync::Arc::new(arc_swap::ArcSwap::from_pointee(rulesets))
+        }
+
+        /// Returns a snapshot of the shared rulesets, without locking
+        pub(crate) fn load(rulesets: &ThreadSafeRuleSets) -> impl Deref<Target=RuleSets> + '_ {
+            rulesets.load_full()
+        }
+
+        /// Returns a snapshot of the shared rulesets.  This never blocks
+        pub(crate) fn try_load(rulesets: &ThreadSafeRuleSets) -> TryLockResult<impl Deref<Target=RuleSets> + '_> {
+            Ok(rulesets.load_full())
+        }
+
+        /// Atomically replace the shared rulesets.  Snapshots loaded earlier are unaffected
+        #[cfg(any(feature="updater", all(test, feature="add_rulesets")))]
+        pub(crate) fn store(rulesets: &ThreadSafeRuleSets, new_rulesets: RuleSets) {
+            rulesets.store(std::sync::Arc::new(new_rulesets));
+        }
+    } else {
+        /// Returns the rulesets wrapped to be shared between threads
+        #[cfg(any(feature="cffi", feature="wasm", all(test, any(feature="updater", feature="add_rulesets"))))]
+        pub(crate) fn new(rulesets: RuleSets) -> ThreadSafeRuleSets {
+            std::sync::Arc::new(std::sync::RwLock::new(rulesets))
+        }
+
+        /// Returns a read guard of the shared rulesets, waiting for any writer
+        pub(crate) fn load(rulesets: &ThreadSafeRuleSets) -> impl Deref<Target=RuleSets> + '_ {
+            rulesets.read().unwrap()
+        }
+
+        /// Returns a read guard of the shared rulesets, or `TryLockError::WouldBlock` if they are
+        /// being written
+        pub(crate) fn try_load(rulesets: &ThreadSafeRuleSets) -> TryLockResult<impl Deref<Target=RuleSets> + '_> {
+            rulesets.try_read()
+        }
+
+        /// Replace the shared rulesets, waiting for any readers
+        #[cfg(any(feature="updater", all(test, feature="add_rulesets")))]
+        pub(crate) fn store(rulesets: &ThreadSafeRuleSets, new_rulesets: RuleSets) {
+            *rulesets.write().unwrap() = new_rulesets;
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::sync::Arc;
+
+use super::{CookieRule, Rule, RuleSet, RuleSets};
+
+const MAGIC: &[u8; 4] = b"HERS";
+const VERSION: u8 = 3;
+
+/// Serializes the parsed structure of a `RuleSets` instance into a compact binary format.
+/// Rulesets shared between several targets are written once and referenced by index.
+pub(crate) fn write(rulesets: &RuleSets) -> Vec<u8> {
+    let mut writer = Writer(Vec::new());
+    writer.0.extend_from_slice(MAGIC);
+    writer.u8(VERSION);
+
+    let mut indices: HashMap<*const RuleSet, u32> = HashMap::new();
+    let mut unique: Vec<&Arc<RuleSet>> = vec![];
+    for rs_vec in rulesets.0.values() {
+        for ruleset in rs_vec {
+            indices.entry(Arc::as_ptr(ruleset)).or_insert_with(|| {
+                unique.push(ruleset);
+                (unique.len() - 1) as u32
+            });
+        }
+    }
+
+    writer.u32(unique.len() as u32);
+    for ruleset in unique {
+        writer.str(&ruleset.name);
+        writer.bool(ruleset.active);
+        writer.bool(ruleset.default_state);
+        writer.opt_str(&ruleset.note);
+        writer.opt_str(&ruleset.exclusions);
+        writer.opt_str(&ruleset.scope);
+        writer.opt_str(&ruleset.source_channel);
+        writer.u32(ruleset.priority as u32);
+
+        writer.u32(ruleset.rules.len() as u32);
+        for rule in &ruleset.rules {
+            match rule {
+                Rule::Trivial => writer.u8(0),
+                Rule::NonTrivial(from_regex, to) => {
+                    writer.u8(1);
+                    writer.str(from_regex);
+                    writer.str(to);
+                }
+            }
+        }
+
+        match &ruleset.cookierules {
+            Some(cookierules) => {
+                writer.bool(true);
+                writer.u32(cookierules.len() as u32);
+                for cookierule in cookierules {
+                    writer.str(&cookierule.host_regex);
+                    writer.str(&cookierule.name_regex);
+                }
+            },
+            None => writer.bool(false),
+        }
+    }
+
+    writer.u32(rulesets.0.len() as u32);
+    for (target, rs_vec) in &rulesets.0 {
+        writer.str(target);
+        writer.u32(rs_vec.len() as u32);
+        for ruleset in rs_vec {
+            writer.u32(indices[&Arc::as_ptr(ruleset)]);
+        }
+    }
+
+    writer.0
+}
+
+/// Reconstructs a `RuleSets` instance from the output of `write`.  Returns `None` if the bytes
+/// are not a valid snapshot
+pub(crate) fn read(bytes: &[u8]) -> Option<RuleSets> {
+    let mut reader = Reader { bytes, pos: 0 };
+    if reader.take(MAGIC.len())? != MAGIC || reader.u8()? != VERSION {
+        return None;
+    }
+
+    let mut scopes: HashMap<Option<String>, Arc<Option<String>>> = HashMap::new();
+    let mut source_channels: HashMap<Option<String>, Arc<Option<String>>> = HashMap::new();
+    let ruleset_count = reader.u32()?;
+    let mut unique = vec![];
+    for _ in 0..ruleset_count {
+        let name = reader.str()?;
+        let active = reader.bool()?;
+        let default_state = reader.bool()?;
+        let note = reader.opt_str()?;
+        let exclusions = reader.opt_str()?;
+        let scope = reader.opt_str()?;
+        let scope = Arc::clone(scopes.entry(scope.clone()).or_insert_with(|| Arc::new(scope)));
+        let source_channel = reader.opt_str()?;
+        let source_channel = Arc::clone(source_channels.entry(source_channel.clone()).or_insert_with(|| Arc::new(source_channel)));
+        let priority = reader.u32()? as i32;
+
+        let mut ruleset = RuleSet::new(name, scope);
+        ruleset.source_channel = source_channel;
+        ruleset.priority = priority;
+        ruleset.active = active;
+        ruleset.default_state = default_state;
+        ruleset.note = note;
+        ruleset.exclusions = exclusions;
+
+        for _ in 0..reader.u32()? {
+            ruleset.rules.push(match reader.u8()? {
+                0 => Rule::Trivial,
+                1 => Rule::NonTrivial(reader.str()?, reader.str()?),
+                _ => return None,
+            });
+        }
+
+        if reader.bool()? {
+            let mut cookierules = vec![];
+            for _ in 0..reader.u32()? {
+                cookierules.push(CookieRule::new(reader.str()?, reader.str()?));
+            }
+            ruleset.cookierules = Some(cookierules);
+        }
+
+        unique.push(Arc::new(ruleset));
+    }
+
+    let mut rulesets = RuleSets::new();
+    for _ in 0..reader.u32()? {
+        let target = reader.str()?;
+        for _ in 0..reader.u32()? {
+            rulesets.insert(&target, Arc::clone(unique.get(reader.u32()? as usize)?));
+        }
+    }
+
+    if reader.pos != bytes.len() {
+        return None;
+    }
+    Some(rulesets)
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn str(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.0.extend_from_slice(value.as_bytes());
+    }
+
+    fn opt_str(&mut self, value: &Option<String>) {
+        match value {
+            Some(value) => {
+                self.bool(true);
+                self.str(value);
+            },
+            None => self.bool(false),
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let slice = self.bytes.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(slice)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Option<bool> {
+        match self.u8()? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn str(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+
+    fn opt_str(&mut self) -> Option<Option<String>> {
+        if self.bool()? {
+            Some(Some(self.str()?))
+        } else {
+            Some(None)
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::RuleSet;
+
+/// The most labels of a host looked up without allocating, since hosts are at most 255 bytes long
+/// unless they are malformed
+const MAX_LABELS: usize = 128;
+
+/// The form of the candidate target under which rulesets were found for a host, see
+/// `TargetIndex::lookup_with`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Candidate {
+    /// The host itself
+    Exact,
+    /// The host with its right-most label replaced by a wildcard
+    RightWildcard,
+    /// The right-most labels of the host, preceded by a single wildcard
+    LeftWildcard { labels: usize },
+    /// The right-most labels of the host, with every preceding label replaced by a wildcard
+    LeftWildcards { labels: usize },
+}
+
+/// An index of ruleset targets as a trie of their labels, from right to left, so that the
+/// rulesets for every candidate target of a host are found by walking the trie alongside the
+/// labels of the host, rather than building and looking up each candidate target string.
+/// Wildcard labels are stored as `*` children
+#[derive(Debug, Default, Clone)]
+pub(crate) struct TargetIndex {
+    rulesets: Vec<Arc<RuleSet>>,
+    children: HashMap<String, TargetIndex>,
+}
+
+impl TargetIndex {
+    /// Index a ruleset under the given target
+    pub(crate) fn insert(&mut self, target: &str, ruleset: Arc<RuleSet>) {
+        let mut node = self;
+        for label in target.rsplit('.') {
+            node = node.children.entry(label.to_string()).or_default();
+        }
+        node.rulesets.push(ruleset);
+    }
+
+    /// Remove all targets from the index
+    pub(crate) fn clear(&mut self) {
+        self.rulesets.clear();
+        self.children.clear();
+    }
+
+    fn child(&self, label: &str) -> Option<&TargetIndex> {
+        self.children.get(label)
+    }
+
+    fn wildcards(&self, count: usize) -> Option<&TargetIndex> {
+        (0..count).try_fold(self, |node, _| node.child("*"))
+    }
+
+    /// Append the rulesets for each candidate target of a well-formed host to `results`, in the
+    /// order documented by `RuleSets::potentially_applicable`
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - A host without a trailing dot or empty labels
+    /// * `results` - The vector to append the rulesets to
+    pub(crate) fn lookup(&self, host: &str, results: &mut Vec<Arc<RuleSet>>) {
+        self.lookup_with(host, |rulesets, _| results.extend(rulesets.iter().cloned()));
+    }
+
+    /// Call `found` with the rulesets for each candidate target of a well-formed host, along with
+    /// the form of the candidate, in the order documented by `RuleSets::potentially_applicable`
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - A host without a trailing dot or empty labels
+    /// * `found` - The function to call with the rulesets found for each candidate
+    pub(crate) fn lookup_with<F: FnMut(&[Arc<RuleSet>], Candidate)>(&self, host: &str, found: F) {
+        // path[depth] is the node for the right-most `depth` labels of the host, if targets exist
+        // for them.  This is kept on the stack for well-formed hosts, so that the only allocations
+        // made are for results
+        let labels = host.split('.').count();
+        if labels <= MAX_LABELS {
+            let mut path: [Option<&TargetIndex>; MAX_LABELS + 1] = [None; MAX_LABELS + 1];
+            self.lookup_along(host, &mut path[..=labels], found);
+        } else {
+            self.lookup_along(host, &mut vec![None; labels + 1], found);
+        }
+    }
+
+    /// Call `found` as for `lookup_with`, filling in `path`, which has room for one more node than
+    /// the host has labels
+    fn lookup_along<'a, F: FnMut(&[Arc<RuleSet>], Candidate)>(&'a self, host: &str, path: &mut [Option<&'a TargetIndex>], mut found: F) {
+        path[0] = Some(self);
+        let mut len = 0;
+        for label in host.rsplit('.') {
+            len += 1;
+            path[len] = path[len - 1].and_then(|node| node.child(label));
+        }
+
+        let mut extend = |node: Option<&TargetIndex>, candidate: Candidate| {
+            if let Some(node) = node {
+                if !node.rulesets.is_empty() {
+                    found(&node.rulesets, candidate);
+                }
+            }
+        };
+
+        // a.b.example.com
+        extend(path[len], Candidate::Exact);
+
+        // a.b.example.*
+        let mut node = self.child("*");
+        for label in host.rsplit('.').skip(1) {
+            node = node.and_then(|node| node.child(label));
+        }
+        extend(node, Candidate::RightWildcard);
+
+        // *.b.example.com, *.example.com, *.com
+        for depth in (1..len).rev() {
+            extend(path[depth].and_then(|node| node.child("*")), Candidate::LeftWildcard { labels: depth });
+        }
+
+        // *.*.example.com, *.*.*.com
+        for wildcards in 2..len {
+            extend(path[len - wildcards].and_then(|node| node.wildcards(wildcards)), Candidate::LeftWildcards { labels: len - wildcards });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looks_up_hosts_with_many_labels() {
+        let ruleset = Arc::new(RuleSet::new(String::from("Deep"), Arc::new(None)));
+        let mut index = TargetIndex::default();
+        index.insert("*.com", Arc::clone(&ruleset));
+
+        let mut results = vec![];
+        index.lookup(&format!("{}com", "a.".repeat(MAX_LABELS - 1)), &mut results);
+        assert_eq!(results.len(), 1);
+
+        results.clear();
+        index.lookup(&format!("{}com", "a.".repeat(MAX_LABELS)), &mut results);
+        assert_eq!(results.len(), 1);
+    }
+}
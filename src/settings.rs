@@ -1,23 +1,67 @@
 use crate::storage::{ThreadSafeStorage};
+use publicsuffix::Psl;
 use serde_json::Value;
 use crate::strings::ERROR_SERDE_PARSE;
-use std::collections::HashSet;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
 use std::iter::FromIterator;
 use url::Host;
 
+/// A callback invoked with the storage key of a setting whenever that setting changes
+pub type SettingsChangeCallback = Box<dyn Fn(&str) + Send>;
+
+/// An error encountered while changing settings
+#[derive(Debug, PartialEq, Clone)]
+pub enum SettingsError {
+    /// The imported string is not valid JSON
+    Parse(String),
+    /// The imported JSON is not an array
+    NotAnArray,
+    /// An entry of the imported JSON array is not a valid host
+    InvalidHost(String),
+    /// The host has no registrable domain, as for IP addresses and public suffixes
+    NoRegistrableDomain(String),
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SettingsError::Parse(message) => write!(f, "Could not parse the imported JSON: {}", message),
+            SettingsError::NotAnArray => write!(f, "The imported JSON is not an array"),
+            SettingsError::InvalidHost(host) => write!(f, "{} is not a valid host", host),
+            SettingsError::NoRegistrableDomain(host) => write!(f, "{} has no registrable domain", host),
+        }
+    }
+}
+
+impl Error for SettingsError {}
+
 /// A high-level abstracton over the storage object which sets and gets global settings
-/// `enabled` and `ease` are cached settings, wrapped in the first-layer `Option` for whether they
-/// are cached or not, and the second-layer `Option` for wheter they exist in `storage` or not.
+/// `enabled` and `ease` are cached when the settings are created, and feature flags when they are
+/// first retrieved, as `None` if they do not exist in `storage`.  Like the sets of hosts, cached
+/// settings are updated by the setters, so reading them never touches `storage`, and changes
+/// made to `storage` by other means are not seen
 pub struct Settings {
     pub storage: ThreadSafeStorage,
-    enabled: Option<Option<bool>>,
-    ease: Option<Option<bool>>,
-    sites_disabled: HashSet<Host>
+    enabled: Option<bool>,
+    ease: Option<bool>,
+    flags: RefCell<HashMap<String, Option<bool>>>,
+    sites_disabled: HashSet<Host>,
+    domains_disabled: HashSet<Host>,
+    ease_exempt_sites: HashSet<Host>,
+    change_callbacks: Vec<SettingsChangeCallback>,
 }
 
 use std::sync::{Arc, Mutex};
 pub type ThreadSafeSettings = Arc<Mutex<Settings>>;
 
+/// Whether HTTPS Everywhere is enabled on a fresh profile
+pub const DEFAULT_HTTPS_EVERYWHERE_ENABLED: bool = true;
+/// Whether EASE (Encrypt All Sites Eligible) mode is enabled on a fresh profile
+pub const DEFAULT_EASE_MODE_ENABLED: bool = false;
+
 impl Settings {
     /// Returns a struct for retrieving and storing global settings
     ///
@@ -25,118 +69,327 @@ impl Settings {
     ///
     /// * `storage` - The storage engine for key-value pairs, wrapped in an Arc<Mutex>
     pub fn new(storage: ThreadSafeStorage) -> Settings {
-        let mut settings = Settings { storage, enabled: None, ease: None, sites_disabled: HashSet::new() };
-        settings.load_sites_disabled();
+        let mut settings = Settings { storage, enabled: None, ease: None, flags: RefCell::new(HashMap::new()), sites_disabled: HashSet::new(), domains_disabled: HashSet::new(), ease_exempt_sites: HashSet::new(), change_callbacks: vec![] };
+        settings.load_bools();
+        settings.load_host_sets();
         settings
     }
 
-    fn cache_enabled(&mut self) {
-        if self.enabled.is_none() {
-            self.enabled = Some(self.storage.lock().unwrap().get_bool(String::from("global_enabled")));
+    /// Returns a struct for retrieving and storing global settings, first storing the default
+    /// values of `global_enabled` and `http_nowhere_on` if requested.  Values which are already
+    /// stored are never overwritten, so this is safe to call on every run
+    ///
+    /// # Arguments
+    ///
+    /// * `storage` - The storage engine for key-value pairs, wrapped in an Arc<Mutex>
+    /// * `seed_defaults` - Whether to store the default values of unset settings
+    pub fn with_defaults(storage: ThreadSafeStorage, seed_defaults: bool) -> Settings {
+        if seed_defaults {
+            let mut storage = storage.lock().unwrap();
+            for (key, default) in [("global_enabled", DEFAULT_HTTPS_EVERYWHERE_ENABLED), ("http_nowhere_on", DEFAULT_EASE_MODE_ENABLED)] {
+                if storage.get_bool(String::from(key)).is_none() {
+                    storage.set_bool(String::from(key), default);
+                }
+            }
         }
+        Settings::new(storage)
+    }
+
+    /// Register a callback which is invoked with the storage key of a setting (`global_enabled`,
+    /// `http_nowhere_on`, `sites_disabled`, `domains_disabled`, `ease_exempt_sites`, or
+    /// `flag: {name}`) whenever that setting is changed
+    ///
+    /// # Arguments
+    ///
+    /// * `callback` - The function to call on changes
+    pub fn on_change(&mut self, callback: SettingsChangeCallback) {
+        self.change_callbacks.push(callback);
+    }
+
+    fn notify_change(&self, key: &str) {
+        for callback in &self.change_callbacks {
+            callback(key);
+        }
+    }
+
+    /// Load whether HTTPS Everywhere and EASE mode are enabled from the storage engine
+    fn load_bools(&mut self) {
+        let storage = self.storage.lock().unwrap();
+        self.enabled = storage.get_bool(String::from("global_enabled"));
+        self.ease = storage.get_bool(String::from("http_nowhere_on"));
     }
 
     /// Retrieve whether HTTPS Everywhere is enabled
     pub fn get_https_everywhere_enabled(&mut self) -> Option<bool> {
-        self.cache_enabled();
-        self.enabled.unwrap()
+        self.enabled
     }
 
     /// Retrieve whether HTTPS Everywhere is enabled. If no value is able to be retrieved, return
     /// the default value provided
     pub fn get_https_everywhere_enabled_or(&mut self, default: bool) -> bool {
-        self.cache_enabled();
-        match self.enabled.unwrap() {
-            Some(value) => value,
-            None => default,
-        }
+        self.enabled.unwrap_or(default)
     }
 
     /// Set HTTPS Everywhere to enabled or disabled
     pub fn set_https_everywhere_enabled(&mut self, value: bool) {
         self.storage.lock().unwrap().set_bool(String::from("global_enabled"), value);
-        self.enabled = Some(Some(value));
-    }
-
-    fn cache_ease(&mut self) {
-        if self.ease.is_none() {
-            self.ease = Some(self.storage.lock().unwrap().get_bool(String::from("http_nowhere_on")));
-        }
+        self.enabled = Some(value);
+        self.notify_change("global_enabled");
     }
 
     /// Retrieve whether EASE (Encrypt All Sites Eligible) mode is enabled
     pub fn get_ease_mode_enabled(&mut self) -> Option<bool> {
-        self.cache_ease();
-        self.ease.unwrap()
+        self.ease
     }
 
     /// Retrieve whether EASE (Encrypt All Sites Eligible) mode is enabled. If no value is able to
     /// be retrieved, return the default value provided
     pub fn get_ease_mode_enabled_or(&mut self, default: bool) -> bool {
-        self.cache_ease();
-        match self.ease.unwrap() {
-            Some(value) => value,
-            None => default,
-        }
+        self.ease.unwrap_or(default)
     }
 
     /// Set EASE (Encrypt All Sites Eligible) mode to enabled or disabled
     pub fn set_ease_mode_enabled(&mut self, value: bool) {
         self.storage.lock().unwrap().set_bool(String::from("http_nowhere_on"), value);
-        self.ease = Some(Some(value));
+        self.ease = Some(value);
+        self.notify_change("http_nowhere_on");
+    }
+
+    /// Retrieve an arbitrary boolean feature flag.  Flags are namespaced in storage under
+    /// `flag: {name}` so that they cannot collide with internal keys such as `sites_disabled`
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the flag
+    pub fn get_flag(&self, name: &str) -> Option<bool> {
+        let mut flags = self.flags.borrow_mut();
+        *flags.entry(String::from(name))
+            .or_insert_with(|| self.storage.lock().unwrap().get_bool(Self::flag_key(name)))
+    }
+
+    /// Retrieve an arbitrary boolean feature flag. If no value is able to be retrieved, return the
+    /// default value provided
+    pub fn get_flag_or(&self, name: &str, default: bool) -> bool {
+        self.get_flag(name).unwrap_or(default)
     }
 
-    /// Load the sites that are disabled from the storage engine
-    fn load_sites_disabled(&mut self) {
-        self.sites_disabled = match self.storage.lock().unwrap().get_string(String::from("sites_disabled")) {
-            Some(sites_disabled_string) => {
-                if let Value::Array(sites_disabled) = serde_json::from_str(&sites_disabled_string).expect(ERROR_SERDE_PARSE) {
-                    HashSet::from_iter(sites_disabled.iter().filter_map(|site_disabled_json| {
-                        match site_disabled_json {
-                            Value::String(site_disabled) => Some(Host::parse(site_disabled).unwrap()),
+    /// Set an arbitrary boolean feature flag
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the flag
+    /// * `value` - The value to set the flag to
+    pub fn set_flag(&mut self, name: &str, value: bool) {
+        let key = Self::flag_key(name);
+        self.storage.lock().unwrap().set_bool(key.clone(), value);
+        self.flags.borrow_mut().insert(String::from(name), Some(value));
+        self.notify_change(&key);
+    }
+
+    fn flag_key(name: &str) -> String {
+        format!("flag: {}", name)
+    }
+
+    /// Load a set of hosts stored as a JSON array under the given key from the storage engine
+    fn load_host_set(&self, key: &str) -> HashSet<Host> {
+        match self.storage.lock().unwrap().get_string(String::from(key)) {
+            Some(hosts_string) => {
+                if let Value::Array(hosts) = serde_json::from_str(&hosts_string).expect(ERROR_SERDE_PARSE) {
+                    HashSet::from_iter(hosts.iter().filter_map(|host_json| {
+                        match host_json {
+                            Value::String(host) => Some(Host::parse(host).unwrap()),
                             _ => None
                         }
                     }))
                 } else {
-                    panic!("Unexpected: disabled sites is not an array");
+                    panic!("Unexpected: {} is not an array", key);
                 }
             },
             None => HashSet::new()
         }
     }
 
-    /// Store the sites that are disabled to the storage engine
-    fn store_sites_disabled(&mut self) {
-        let sites_disabled_json: Value = self.sites_disabled.iter().map(|site_disabled| Value::String(site_disabled.to_string())).collect();
-        self.storage.lock().unwrap().set_string(String::from("sites_disabled"), sites_disabled_json.to_string());
+    /// Serialize a set of hosts as a sorted JSON array
+    fn host_set_to_json(hosts: &HashSet<Host>) -> String {
+        let mut hosts: Vec<String> = hosts.iter().map(|host| host.to_string()).collect();
+        hosts.sort();
+        Value::from(hosts).to_string()
+    }
+
+    /// Parse a JSON array of hosts, failing on any entry which is not a valid host
+    fn parse_host_set(hosts_string: &str) -> Result<HashSet<Host>, SettingsError> {
+        let hosts = match serde_json::from_str(hosts_string) {
+            Ok(Value::Array(hosts)) => hosts,
+            Ok(_) => return Err(SettingsError::NotAnArray),
+            Err(err) => return Err(SettingsError::Parse(err.to_string()))
+        };
+        hosts.iter().map(|host_json| {
+            match host_json {
+                Value::String(host) => Host::parse(host).map_err(|_| SettingsError::InvalidHost(host.clone())),
+                _ => Err(SettingsError::InvalidHost(host_json.to_string()))
+            }
+        }).collect()
+    }
+
+    /// Store a set of hosts as a JSON array under the given key to the storage engine
+    fn store_host_set(&self, key: &str, hosts: &HashSet<Host>) {
+        self.storage.lock().unwrap().set_string(String::from(key), Self::host_set_to_json(hosts));
+        self.notify_change(key);
+    }
+
+    /// Load the sites that are disabled and the sites exempt from EASE mode from the storage engine
+    fn load_host_sets(&mut self) {
+        self.sites_disabled = self.load_host_set("sites_disabled");
+        self.domains_disabled = self.load_host_set("domains_disabled");
+        self.ease_exempt_sites = self.load_host_set("ease_exempt_sites");
     }
 
+    /// Refresh the cached settings and sets of hosts from the storage engine, for when it has been
+    /// changed by other means, such as by another `Settings` instance.  A set of hosts which is
+    /// not stored as a valid JSON array of hosts is left as it was, and the first such error is
+    /// returned once all other settings have been refreshed
+    pub fn reload(&mut self) -> Result<(), SettingsError> {
+        self.load_bools();
+        self.flags.borrow_mut().clear();
+
+        let mut result = Ok(());
+        for (key, hosts) in [
+            ("sites_disabled", &mut self.sites_disabled),
+            ("domains_disabled", &mut self.domains_disabled),
+            ("ease_exempt_sites", &mut self.ease_exempt_sites),
+        ] {
+            let stored = self.storage.lock().unwrap().get_string(String::from(key));
+            match stored.as_deref().map(Self::parse_host_set) {
+                Some(Ok(stored_hosts)) => *hosts = stored_hosts,
+                Some(Err(err)) => result = result.and(Err(err)),
+                None => hosts.clear(),
+            }
+        }
+        result
+    }
 
     /// Provide a Url::Host object to disable or enable a site
     pub fn set_site_disabled(&mut self, site: Host, set_disabled: bool) {
-        let currently_disabled = self.get_site_disabled(&site);
+        let currently_disabled = self.sites_disabled.contains(&site);
         if currently_disabled && !set_disabled {
             self.sites_disabled.remove(&site);
-            self.store_sites_disabled();
+            self.store_host_set("sites_disabled", &self.sites_disabled);
         } else if !currently_disabled && set_disabled {
             self.sites_disabled.insert(site);
-            self.store_sites_disabled();
+            self.store_host_set("sites_disabled", &self.sites_disabled);
         }
     }
 
+    /// Returns whether a site is disabled, either as an exact host by `set_site_disabled` or as a
+    /// subdomain of a registrable domain disabled by `set_domain_disabled`
     pub fn get_site_disabled(&self, site: &Host) -> bool {
-       self.sites_disabled.contains(site)
+       self.sites_disabled.contains(site) || self.get_domain_disabled(site)
     }
 
     pub fn get_sites_disabled(&self) -> &HashSet<Host> {
         &self.sites_disabled
     }
+
+    /// Disable or enable a site along with all of its subdomains, by its registrable domain (eTLD+1)
+    /// as determined by the given public suffix list.  Disabling `www.example.com` disables
+    /// `example.com` and all its subdomains.  This is independent of the exact hosts disabled by
+    /// `set_site_disabled`.  Returns the registrable domain, or an error if the site has none
+    ///
+    /// # Arguments
+    ///
+    /// * `site` - The site to disable or enable the registrable domain of
+    /// * `list` - The public suffix list
+    /// * `set_disabled` - Whether to disable or enable the domain
+    pub fn set_domain_disabled<L: Psl>(&mut self, site: &Host, list: &L, set_disabled: bool) -> Result<Host, SettingsError> {
+        let domain = match site {
+            Host::Domain(domain) => list.domain(domain.as_bytes()),
+            _ => None
+        };
+        let domain = match domain.map(|domain| std::str::from_utf8(domain.as_bytes()).map(Host::parse)) {
+            Some(Ok(Ok(domain))) => domain,
+            _ => return Err(SettingsError::NoRegistrableDomain(site.to_string()))
+        };
+
+        let changed = if set_disabled {
+            self.domains_disabled.insert(domain.clone())
+        } else {
+            self.domains_disabled.remove(&domain)
+        };
+        if changed {
+            self.store_host_set("domains_disabled", &self.domains_disabled);
+        }
+        Ok(domain)
+    }
+
+    /// Returns whether the site is covered by a registrable domain disabled by
+    /// `set_domain_disabled`
+    pub fn get_domain_disabled(&self, site: &Host) -> bool {
+        if self.domains_disabled.is_empty() {
+            return false;
+        }
+        if let Host::Domain(domain) = site {
+            let mut parent = &domain[..];
+            loop {
+                if self.domains_disabled.contains(&Host::Domain(parent.to_string())) {
+                    return true;
+                }
+                match parent.find('.') {
+                    Some(index) => parent = &parent[index + 1..],
+                    None => return false
+                }
+            }
+        }
+        false
+    }
+
+    pub fn get_domains_disabled(&self) -> &HashSet<Host> {
+        &self.domains_disabled
+    }
+
+    /// Export the disabled sites as a JSON array of hosts, for backup or syncing across devices
+    pub fn export_sites_disabled(&self) -> String {
+        Self::host_set_to_json(&self.sites_disabled)
+    }
+
+    /// Replace the disabled sites with those in a JSON array of hosts, as exported by
+    /// `export_sites_disabled`.  If any entry is not a valid host, the disabled sites are left
+    /// unchanged and an error is returned
+    ///
+    /// # Arguments
+    ///
+    /// * `json` - A JSON array of hosts
+    pub fn import_sites_disabled(&mut self, json: &str) -> Result<(), SettingsError> {
+        self.sites_disabled = Self::parse_host_set(json)?;
+        self.store_host_set("sites_disabled", &self.sites_disabled);
+        Ok(())
+    }
+
+    /// Provide a Url::Host object to exempt a site from, or subject it to, EASE mode request
+    /// cancellation
+    pub fn set_site_ease_exempt(&mut self, site: Host, set_exempt: bool) {
+        let currently_exempt = self.get_site_ease_exempt(&site);
+        if currently_exempt && !set_exempt {
+            self.ease_exempt_sites.remove(&site);
+            self.store_host_set("ease_exempt_sites", &self.ease_exempt_sites);
+        } else if !currently_exempt && set_exempt {
+            self.ease_exempt_sites.insert(site);
+            self.store_host_set("ease_exempt_sites", &self.ease_exempt_sites);
+        }
+    }
+
+    pub fn get_site_ease_exempt(&self, site: &Host) -> bool {
+       self.ease_exempt_sites.contains(site)
+    }
+
+    pub fn get_ease_exempt_sites(&self) -> &HashSet<Host> {
+        &self.ease_exempt_sites
+    }
 }
 
 #[cfg(test)]
 mod tests{
     use super::*;
-    use crate::storage::tests::working_storage::WorkingTempStorage;
+    use crate::storage::{Storage, tests::working_storage::WorkingTempStorage};
     use std::sync::{Arc, Mutex};
     use std::thread;
     
@@ -153,6 +406,167 @@ mod tests{
         assert_eq!(settings.get_ease_mode_enabled_or(false), false);
     }
 
+    #[test]
+    fn seeds_defaults_without_overwriting() {
+        let storage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let mut settings = Settings::with_defaults(storage.clone(), false);
+        assert_eq!(settings.get_https_everywhere_enabled(), None);
+
+        let mut settings = Settings::with_defaults(storage.clone(), true);
+        assert_eq!(settings.get_https_everywhere_enabled(), Some(true));
+        assert_eq!(settings.get_ease_mode_enabled(), Some(false));
+
+        settings.set_https_everywhere_enabled(false);
+        let mut settings = Settings::with_defaults(storage, true);
+        assert_eq!(settings.get_https_everywhere_enabled(), Some(false));
+    }
+
+    #[test]
+    fn gets_and_sets_flags() {
+        let mut settings = Settings::new(Arc::new(Mutex::new(WorkingTempStorage::new())));
+        assert_eq!(settings.get_flag("show_counter"), None);
+        assert!(settings.get_flag_or("show_counter", true));
+
+        settings.set_flag("show_counter", false);
+        assert_eq!(settings.get_flag("show_counter"), Some(false));
+        assert_eq!(settings.storage.lock().unwrap().get_bool(String::from("show_counter")), None);
+    }
+
+    #[test]
+    fn caches_reads() {
+        let storage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let mut settings = Settings::new(storage.clone());
+        settings.set_ease_mode_enabled(true);
+        assert_eq!(settings.get_flag("show_counter"), None);
+
+        // Changes made to storage by other means are not seen once a setting is cached
+        storage.lock().unwrap().set_bool(String::from("global_enabled"), false);
+        storage.lock().unwrap().set_bool(String::from("http_nowhere_on"), false);
+        storage.lock().unwrap().set_bool(String::from("flag: show_counter"), true);
+        assert_eq!(settings.get_https_everywhere_enabled(), None);
+        assert!(settings.get_ease_mode_enabled_or(false));
+        assert_eq!(settings.get_flag("show_counter"), None);
+
+        let mut settings = Settings::new(storage);
+        assert_eq!(settings.get_https_everywhere_enabled(), Some(false));
+        assert_eq!(settings.get_flag("show_counter"), Some(true));
+    }
+
+    #[test]
+    fn reloads() {
+        let storage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let mut settings = Settings::new(storage.clone());
+        assert_eq!(settings.get_flag("show_counter"), None);
+
+        let mut other = Settings::new(storage.clone());
+        other.set_https_everywhere_enabled(false);
+        other.set_flag("show_counter", true);
+        other.set_site_disabled(Host::parse("example.com").unwrap(), true);
+        other.set_site_ease_exempt(Host::parse("intranet.corp").unwrap(), true);
+
+        assert_eq!(settings.reload(), Ok(()));
+        assert_eq!(settings.get_https_everywhere_enabled(), Some(false));
+        assert_eq!(settings.get_flag("show_counter"), Some(true));
+        assert!(settings.get_site_disabled(&Host::parse("example.com").unwrap()));
+
+        storage.lock().unwrap().set_string(String::from("sites_disabled"), String::from("[\"example.com\""));
+        storage.lock().unwrap().remove(String::from("ease_exempt_sites"));
+        assert!(matches!(settings.reload(), Err(SettingsError::Parse(_))));
+        assert!(settings.get_site_disabled(&Host::parse("example.com").unwrap()));
+        assert!(!settings.get_site_ease_exempt(&Host::parse("intranet.corp").unwrap()));
+    }
+
+    #[test]
+    fn stores_ease_exempt_sites() {
+        let storage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let mut settings = Settings::new(storage.clone());
+        settings.set_site_ease_exempt(Host::parse("intranet.corp").unwrap(), true);
+        assert!(settings.get_site_ease_exempt(&Host::parse("intranet.corp").unwrap()));
+
+        let settings = Settings::new(storage);
+        assert!(settings.get_site_ease_exempt(&Host::parse("intranet.corp").unwrap()));
+        assert!(!settings.get_site_ease_exempt(&Host::parse("example.com").unwrap()));
+    }
+
+    #[test]
+    fn exports_and_imports_sites_disabled() {
+        let storage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let mut settings = Settings::new(storage.clone());
+        settings.set_site_disabled(Host::parse("example.com").unwrap(), true);
+        settings.set_site_disabled(Host::parse("example.org").unwrap(), true);
+        let exported = settings.export_sites_disabled();
+        assert_eq!(exported, r#"["example.com","example.org"]"#);
+
+        let mut other = Settings::new(Arc::new(Mutex::new(WorkingTempStorage::new())));
+        other.set_site_disabled(Host::parse("example.net").unwrap(), true);
+        other.import_sites_disabled(&exported).unwrap();
+        assert_eq!(other.get_sites_disabled(), settings.get_sites_disabled());
+
+        assert_eq!(other.import_sites_disabled("{}"), Err(SettingsError::NotAnArray));
+        assert!(matches!(other.import_sites_disabled("[\"example.com\""), Err(SettingsError::Parse(_))));
+        assert_eq!(other.import_sites_disabled(r#"["example.net", "exa mple.com"]"#), Err(SettingsError::InvalidHost(String::from("exa mple.com"))));
+        assert_eq!(other.import_sites_disabled(r#"["example.net", 1]"#), Err(SettingsError::InvalidHost(String::from("1"))));
+        assert_eq!(other.get_sites_disabled(), settings.get_sites_disabled());
+    }
+
+    #[test]
+    fn disables_registrable_domains() {
+        let list: publicsuffix::List = "// ===BEGIN ICANN DOMAINS===\ncom\nco.uk\n".parse().unwrap();
+        let storage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let mut settings = Settings::new(storage.clone());
+
+        assert_eq!(settings.set_domain_disabled(&Host::parse("www.example.co.uk").unwrap(), &list, true), Ok(Host::parse("example.co.uk").unwrap()));
+        assert!(settings.get_site_disabled(&Host::parse("example.co.uk").unwrap()));
+        assert!(settings.get_site_disabled(&Host::parse("a.b.example.co.uk").unwrap()));
+        assert!(!settings.get_site_disabled(&Host::parse("notexample.co.uk").unwrap()));
+        assert!(!settings.get_site_disabled(&Host::parse("other.co.uk").unwrap()));
+        assert!(!settings.get_sites_disabled().contains(&Host::parse("example.co.uk").unwrap()));
+
+        assert_eq!(settings.set_domain_disabled(&Host::parse("co.uk").unwrap(), &list, true), Err(SettingsError::NoRegistrableDomain(String::from("co.uk"))));
+        assert_eq!(settings.set_domain_disabled(&Host::parse("127.0.0.1").unwrap(), &list, true), Err(SettingsError::NoRegistrableDomain(String::from("127.0.0.1"))));
+
+        let mut settings = Settings::new(storage);
+        assert!(settings.get_site_disabled(&Host::parse("www.example.co.uk").unwrap()));
+        settings.set_domain_disabled(&Host::parse("example.co.uk").unwrap(), &list, false).unwrap();
+        assert!(!settings.get_site_disabled(&Host::parse("www.example.co.uk").unwrap()));
+    }
+
+    #[test]
+    fn disables_sites_independently_of_domains() {
+        let list: publicsuffix::List = "// ===BEGIN ICANN DOMAINS===\ncom\n".parse().unwrap();
+        let mut settings = Settings::new(Arc::new(Mutex::new(WorkingTempStorage::new())));
+        let changed = Arc::new(Mutex::new(vec![]));
+        let changed_clone = Arc::clone(&changed);
+        settings.on_change(Box::new(move |key| changed_clone.lock().unwrap().push(key.to_string())));
+
+        settings.set_domain_disabled(&Host::parse("example.com").unwrap(), &list, true).unwrap();
+        settings.set_site_disabled(Host::parse("www.example.com").unwrap(), true);
+        settings.set_site_disabled(Host::parse("mail.example.com").unwrap(), false);
+        assert!(settings.get_sites_disabled().contains(&Host::parse("www.example.com").unwrap()));
+        assert!(settings.get_site_disabled(&Host::parse("mail.example.com").unwrap()));
+
+        settings.set_domain_disabled(&Host::parse("example.com").unwrap(), &list, false).unwrap();
+        assert!(settings.get_site_disabled(&Host::parse("www.example.com").unwrap()));
+        assert!(!settings.get_site_disabled(&Host::parse("mail.example.com").unwrap()));
+
+        assert_eq!(*changed.lock().unwrap(), vec!["domains_disabled", "sites_disabled", "domains_disabled"]);
+    }
+
+    #[test]
+    fn notifies_on_change() {
+        let mut settings = Settings::new(Arc::new(Mutex::new(WorkingTempStorage::new())));
+        let changed = Arc::new(Mutex::new(vec![]));
+        let changed_clone = Arc::clone(&changed);
+        settings.on_change(Box::new(move |key| changed_clone.lock().unwrap().push(key.to_string())));
+
+        settings.set_https_everywhere_enabled(false);
+        settings.set_ease_mode_enabled(true);
+        settings.set_site_disabled(Host::parse("example.com").unwrap(), true);
+        settings.set_site_disabled(Host::parse("example.com").unwrap(), true);
+
+        assert_eq!(*changed.lock().unwrap(), vec!["global_enabled", "http_nowhere_on", "sites_disabled"]);
+    }
+
     #[test]
     fn is_threadsafe() {
         let mut settings = Settings::new(Arc::new(Mutex::new(WorkingTempStorage::new())));
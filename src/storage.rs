@@ -1,3 +1,12 @@
+/// A value of any of the types which may be held by a storage engine
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorageValue {
+    Int(usize),
+    String(String),
+    Bool(bool),
+    Bytes(Vec<u8>),
+}
+
 pub trait Storage {
     /// Get an integer from whatever key-value storage engine implements trait
     fn get_int(&self, key: String) -> Option<usize>;
@@ -15,11 +24,30 @@ pub trait Storage {
     fn get_bytes(&self, key: String) -> Option<Vec<u8>>;
     /// Set bytes for whatever key-value storage engine implements trait
     fn set_bytes(&mut self, key: String, value: Vec<u8>);
+    /// Remove a key and its value, of whatever type, from the key-value storage engine
+    fn remove(&mut self, key: String);
+    /// Set a batch of values for whatever key-value storage engine implements trait.  Engines
+    /// which are able to should override this to write all entries atomically
+    fn set_batch(&mut self, entries: Vec<(String, StorageValue)>) {
+        for (key, value) in entries {
+            match value {
+                StorageValue::Int(value) => self.set_int(key, value),
+                StorageValue::String(value) => self.set_string(key, value),
+                StorageValue::Bool(value) => self.set_bool(key, value),
+                StorageValue::Bytes(value) => self.set_bytes(key, value),
+            }
+        }
+    }
 }
 
 use std::sync::{Arc, Mutex};
 pub type ThreadSafeStorage = Arc<Mutex<dyn Storage + Sync + Send>>;
 
+#[cfg(feature="async-storage")]
+mod async_storage;
+#[cfg(feature="async-storage")]
+pub use async_storage::{AsyncStorage, SyncStorageAdapter};
+
 #[cfg(test)]
 pub mod tests {
 
@@ -33,7 +61,7 @@ pub mod tests {
             fn get_int(&self, _key: String) -> Option<usize> { Some(5) }
             fn set_int(&mut self, _key: String, _value: usize) {}
             fn get_string(&self, key: String) -> Option<String> {
-                if key == String::from("sites_disabled") {
+                if key == "sites_disabled" || key == "domains_disabled" || key == "ease_exempt_sites" {
                     None
                 } else {
                     Some(String::from("test"))
@@ -50,6 +78,7 @@ pub mod tests {
             fn set_bool(&mut self, _key: String, _value: bool) {}
             fn get_bytes(&self, _key: String) -> Option<Vec<u8>> { Some(vec![12]) }
             fn set_bytes(&mut self, _key: String, _value: Vec<u8>) {}
+            fn remove(&mut self, _key: String) {}
         }
 
         pub struct TestStorage;
@@ -133,6 +162,34 @@ pub mod tests {
             fn set_bytes(&mut self, key: String, value: Vec<u8>) {
                 self.bytes.insert(key, value);
             }
+
+            fn remove(&mut self, key: String) {
+                self.ints.remove(&key);
+                self.bools.remove(&key);
+                self.strings.remove(&key);
+                self.bytes.remove(&key);
+            }
         }
     }
+    #[test]
+    #[cfg(all(feature="add_rulesets",any(feature="updater",feature="rewriter")))]
+    fn mock_storage_gets_bytes() {
+        use super::*;
+        let storage = mock_storage::TestStorage;
+        assert_eq!(storage.get_bytes(String::from("bloom: test")), Some(vec![12]));
+    }
+
+    #[test]
+    #[cfg(any(feature="updater",feature="settings"))]
+    fn sets_batch() {
+        use super::*;
+        let mut storage = working_storage::WorkingTempStorage::new();
+        storage.set_batch(vec![
+            (String::from("int"), StorageValue::Int(5)),
+            (String::from("bytes"), StorageValue::Bytes(vec![1, 2])),
+        ]);
+
+        assert_eq!(storage.get_int(String::from("int")), Some(5));
+        assert_eq!(storage.get_bytes(String::from("bytes")), Some(vec![1, 2]));
+    }
 }
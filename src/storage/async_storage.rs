@@ -0,0 +1,148 @@
+use async_trait::async_trait;
+
+use super::{StorageValue, ThreadSafeStorage};
+
+/// An asynchronous counterpart of `Storage`, for key-value storage engines backed by asynchronous
+/// IO.  A synchronous `Storage` is used as an `AsyncStorage` by wrapping it in a
+/// `SyncStorageAdapter`
+#[async_trait]
+pub trait AsyncStorage {
+    /// Get an integer from whatever key-value storage engine implements trait
+    async fn get_int(&self, key: String) -> Option<usize>;
+    /// Set an integer for whatever key-value storage engine implements trait
+    async fn set_int(&mut self, key: String, value: usize);
+    /// Get a string from whatever key-value storage engine implements trait
+    async fn get_string(&self, key: String) -> Option<String>;
+    /// Set a string for whatever key-value storage engine implements trait
+    async fn set_string(&mut self, key: String, value: String);
+    /// Get a boolean from whatever key-value storage engine implements trait
+    async fn get_bool(&self, key: String) -> Option<bool>;
+    /// Set a bool for whatever key-value storage engine implements trait
+    async fn set_bool(&mut self, key: String, value: bool);
+    /// Get bytes from whatever key-value storage engine implements trait
+    async fn get_bytes(&self, key: String) -> Option<Vec<u8>>;
+    /// Set bytes for whatever key-value storage engine implements trait
+    async fn set_bytes(&mut self, key: String, value: Vec<u8>);
+    /// Remove a key and its value, of whatever type, from the key-value storage engine
+    async fn remove(&mut self, key: String);
+    /// Set a batch of values for whatever key-value storage engine implements trait.  Engines
+    /// which are able to should override this to write all entries atomically
+    async fn set_batch(&mut self, entries: Vec<(String, StorageValue)>) {
+        for (key, value) in entries {
+            match value {
+                StorageValue::Int(value) => self.set_int(key, value).await,
+                StorageValue::String(value) => self.set_string(key, value).await,
+                StorageValue::Bool(value) => self.set_bool(key, value).await,
+                StorageValue::Bytes(value) => self.set_bytes(key, value).await,
+            }
+        }
+    }
+}
+
+/// An `AsyncStorage` over a synchronous `Storage`, whose futures complete immediately.  The
+/// storage is shared, so the same storage may be given to `Settings` and the `Updater` while
+/// being used asynchronously elsewhere
+pub struct SyncStorageAdapter {
+    storage: ThreadSafeStorage,
+}
+
+impl SyncStorageAdapter {
+    /// Returns an adapter over the storage specified
+    ///
+    /// # Arguments
+    ///
+    /// * `storage` - A storage object, wrapped in an Arc<Mutex>
+    pub fn new(storage: ThreadSafeStorage) -> SyncStorageAdapter {
+        SyncStorageAdapter { storage }
+    }
+}
+
+#[async_trait]
+impl AsyncStorage for SyncStorageAdapter {
+    async fn get_int(&self, key: String) -> Option<usize> {
+        self.storage.lock().unwrap().get_int(key)
+    }
+
+    async fn set_int(&mut self, key: String, value: usize) {
+        self.storage.lock().unwrap().set_int(key, value);
+    }
+
+    async fn get_string(&self, key: String) -> Option<String> {
+        self.storage.lock().unwrap().get_string(key)
+    }
+
+    async fn set_string(&mut self, key: String, value: String) {
+        self.storage.lock().unwrap().set_string(key, value);
+    }
+
+    async fn get_bool(&self, key: String) -> Option<bool> {
+        self.storage.lock().unwrap().get_bool(key)
+    }
+
+    async fn set_bool(&mut self, key: String, value: bool) {
+        self.storage.lock().unwrap().set_bool(key, value);
+    }
+
+    async fn get_bytes(&self, key: String) -> Option<Vec<u8>> {
+        self.storage.lock().unwrap().get_bytes(key)
+    }
+
+    async fn set_bytes(&mut self, key: String, value: Vec<u8>) {
+        self.storage.lock().unwrap().set_bytes(key, value);
+    }
+
+    async fn remove(&mut self, key: String) {
+        self.storage.lock().unwrap().remove(key);
+    }
+
+    async fn set_batch(&mut self, entries: Vec<(String, StorageValue)>) {
+        self.storage.lock().unwrap().set_batch(entries);
+    }
+}
+
+#[cfg(all(test,any(feature="updater",feature="settings")))]
+mod tests {
+    use super::*;
+    use crate::storage::tests::working_storage::WorkingTempStorage;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+    /// Returns the output of a future which completes without waiting, as those of
+    /// `SyncStorageAdapter` do
+    fn ready<F: Future>(future: F) -> F::Output {
+        fn noop_raw_waker() -> RawWaker {
+            fn clone(_: *const ()) -> RawWaker { noop_raw_waker() }
+            fn noop(_: *const ()) {}
+            static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+            RawWaker::new(std::ptr::null(), &VTABLE)
+        }
+        let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+        let mut future = Box::pin(future);
+        match Pin::as_mut(&mut future).poll(&mut Context::from_waker(&waker)) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("future did not complete immediately"),
+        }
+    }
+
+    #[test]
+    fn adapts_sync_storage() {
+        let storage: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let mut adapter = SyncStorageAdapter::new(Arc::clone(&storage));
+
+        ready(adapter.set_int(String::from("int"), 5));
+        ready(adapter.set_batch(vec![
+            (String::from("string"), StorageValue::String(String::from("test"))),
+            (String::from("bool"), StorageValue::Bool(true)),
+        ]));
+        assert_eq!(ready(adapter.get_int(String::from("int"))), Some(5));
+        assert_eq!(storage.lock().unwrap().get_string(String::from("string")), Some(String::from("test")));
+
+        storage.lock().unwrap().set_bytes(String::from("bytes"), vec![1, 2]);
+        assert_eq!(ready(adapter.get_bytes(String::from("bytes"))), Some(vec![1, 2]));
+
+        ready(adapter.remove(String::from("bool")));
+        assert_eq!(ready(adapter.get_bool(String::from("bool"))), None);
+    }
+}
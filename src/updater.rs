@@ -1,43 +1,91 @@
+#[macro_use]
+mod logging;
+mod builder;
+mod fetcher;
 mod update_channels;
-pub use update_channels::{UpdateChannel, UpdateChannels, UpdateChannelFormat};
+pub use builder::{UpdaterBuilder, DEFAULT_PERIODICITY};
+pub use fetcher::{CacheValidators, Fetcher, HttpReqFetcher, DEFAULT_USER_AGENT};
+pub use update_channels::{SignatureAlgorithm, UpdateChannel, UpdateChannelError, UpdateChannelPolicy, UpdateChannels, UpdateChannelFormat};
 
 use bloomfilter::Bloom;
-use crate::{rulesets::ENABLE_MIXED_RULESETS, rulesets::RULE_ACTIVE_STATES, storage::ThreadSafeStorage, rulesets::ThreadSafeRuleSets};
-use flate2::read::GzDecoder;
-use http_req::request;
+use crate::{RuleSets, rulesets::ENABLE_MIXED_RULESETS, rulesets::RULE_ACTIVE_STATES, storage::{StorageValue, ThreadSafeStorage}, rulesets::{shared, ThreadSafeRuleSets}};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use openssl::error::ErrorStack;
 use openssl::hash::MessageDigest;
-use openssl::pkey::PKey;
+use openssl::pkey::{PKey, Public};
 use openssl::rsa::Padding;
 use openssl::sign::Verifier;
 use ring::{digest, test};
 use serde_json::Value;
-use std::collections::HashMap;
-use std::cmp;
+use std::collections::{BTreeSet, HashMap};
+use std::collections::hash_map::RandomState;
 use std::error::Error;
 use std::fmt;
-use std::io::Read;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Write};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 type Timestamp = usize;
 pub type ThreadSafeBloomVec = Arc<Mutex<Vec<bloomfilter::Bloom<str>>>>;
 
-#[derive(Debug, Clone)]
-struct UpdaterError {
-    error_string: String,
-}
-
-impl UpdaterError {
-    pub fn new(error_string: String) -> UpdaterError {
-        UpdaterError {
-            error_string
-        }
-    }
+/// A callback invoked after stored updates have been applied to the rulesets and bloom filters
+pub type UpdatesAppliedCallback = Box<dyn Fn() + Send + Sync>;
+
+/// An error encountered while updating.  `name` is the name of the update channel concerned, and
+/// `file` a description of the file being downloaded
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpdaterError {
+    /// A request could not be completed
+    NetworkError { name: String, file: String, message: String },
+    /// A non-2XX response was returned
+    BadStatus { name: String, file: String, code: u16 },
+    /// A response exceeded the maximum download size
+    DownloadTooLarge { name: String, file: String, max_bytes: usize },
+    /// A downloaded ruleset bundle could not be decompressed.  `truncated` indicates whether the
+    /// bundle ended unexpectedly, as for an incomplete download which may be retried, rather than
+    /// being corrupt
+    DecompressFailed { name: String, timestamp: Timestamp, truncated: bool },
+    /// A downloaded ruleset bundle decompressed to more than the maximum size
+    DecompressedTooLarge { name: String, max_bytes: usize },
+    /// The signature of a download did not verify with any of the keys of the update channel
+    SignatureInvalid { name: String },
+    /// A certificate of the chain the keys of the update channel were taken from has expired, or
+    /// is not yet valid
+    CertificateExpired { name: String },
+    /// The timestamp within a download did not match the timestamp it was published under
+    TimestampMismatch { name: String },
+    /// A field of the rulesets JSON or bloom filter metadata was missing or malformed
+    MetadataParse { name: String, field: String },
+    /// The checksum of a downloaded bloom filter did not match its metadata
+    ChecksumMismatch { name: String },
+    /// No update channel with the given name and format exists
+    UnknownChannel { name: String },
+    /// No update has been stored for the update channel
+    MissingStoredUpdate { name: String },
+    /// The check was cancelled before the file could be downloaded
+    Cancelled { name: String, file: String },
 }
 
 impl fmt::Display for UpdaterError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.error_string)
+        match self {
+            UpdaterError::NetworkError { name, file, message } => write!(f, "{}: Could not fetch the {} URL: {}", name, file, message),
+            UpdaterError::BadStatus { name, file, code } => write!(f, "{}: A non-2XX response ({}) was returned from the {} URL", name, code, file),
+            UpdaterError::DownloadTooLarge { name, file, max_bytes } => write!(f, "{}: The {} response exceeded the maximum download size of {} bytes", name, file, max_bytes),
+            UpdaterError::DecompressFailed { name, timestamp, truncated: true } => write!(f, "{}: The ruleset bundle for timestamp {} is truncated", name, timestamp),
+            UpdaterError::DecompressFailed { name, timestamp, truncated: false } => write!(f, "{}: The ruleset bundle for timestamp {} is corrupt", name, timestamp),
+            UpdaterError::DecompressedTooLarge { name, max_bytes } => write!(f, "{}: Rulesets decompress to more than the maximum size of {} bytes.  Aborting.", name, max_bytes),
+            UpdaterError::SignatureInvalid { name } => write!(f, "{}: Downloaded signature is invalid.  Aborting.", name),
+            UpdaterError::CertificateExpired { name } => write!(f, "{}: Certificate chain is not currently valid.  Aborting.", name),
+            UpdaterError::TimestampMismatch { name } => write!(f, "{}: JSON timestamp does not match with latest timestamp file", name),
+            UpdaterError::MetadataParse { name, field } => write!(f, "{}: Could not parse JSON `{}`", name, field),
+            UpdaterError::ChecksumMismatch { name } => write!(f, "{}: sha256sum of the bloom filter is invalid.  Aborting.", name),
+            UpdaterError::UnknownChannel { name } => write!(f, "{}: No ruleset update channel with this name exists", name),
+            UpdaterError::MissingStoredUpdate { name } => write!(f, "{}: Could not retrieve stored update", name),
+            UpdaterError::Cancelled { name, file } => write!(f, "{}: The check was cancelled before downloading the {} URL", name, file),
+        }
     }
 }
 
@@ -47,6 +95,104 @@ impl Error for UpdaterError {
     }
 }
 
+/// What happened to a single update channel during a call to `Updater::perform_check`
+#[derive(Debug, PartialEq, Clone)]
+pub enum UpdateChannelStatus {
+    /// The update channel was checked, and no new update was found
+    NoUpdate,
+    /// A new update was found, but was skipped for the given reason
+    Skipped(String),
+    /// The update channel was updated to the given timestamp
+    Updated(Timestamp),
+    /// A new update was found, but it could not be downloaded, verified or stored
+    Errored(String),
+    /// The check was cancelled while the update channel was being checked or updated
+    Cancelled,
+}
+
+/// The outcome of checking a single update channel for updates
+#[derive(Debug, PartialEq, Clone)]
+pub struct UpdateChannelResult {
+    pub name: String,
+    pub status: UpdateChannelStatus,
+}
+
+impl UpdateChannelResult {
+    fn new(name: &str, status: UpdateChannelStatus) -> UpdateChannelResult {
+        UpdateChannelResult {
+            name: name.to_string(),
+            status,
+        }
+    }
+}
+
+/// The stored update state of a single update channel
+#[derive(Debug, PartialEq, Clone)]
+pub struct ChannelStatus {
+    /// When update channels were last checked for updates
+    pub last_checked: Option<Timestamp>,
+    /// The timestamp of the latest update which was downloaded and stored for this channel
+    pub last_seen_timestamp: Option<Timestamp>,
+    /// The timestamp of the latest update which was successfully applied from this channel
+    pub last_applied_timestamp: Option<Timestamp>,
+}
+
+/// The stored update state of the updater, with the status of each update channel keyed by the
+/// update channel name
+#[derive(Debug, PartialEq, Clone)]
+pub struct UpdaterStatus {
+    /// When update channels were last checked for updates
+    pub last_checked: Option<Timestamp>,
+    pub channels: HashMap<String, ChannelStatus>,
+}
+
+/// The result of checking the rulesets stored for an update channel against the stored timestamp
+#[derive(Debug, PartialEq, Clone)]
+pub enum IntegrityStatus {
+    /// The timestamp within the stored rulesets matches the stored timestamp
+    Valid,
+    /// Neither rulesets nor a timestamp are stored for the update channel
+    NotStored,
+    /// Either rulesets or a timestamp are stored for the update channel, but not both
+    Incomplete,
+    /// The stored rulesets could not be decompressed or parsed, for the given reason
+    Corrupt(String),
+    /// The timestamp within the stored rulesets does not match the stored timestamp
+    TimestampMismatch,
+}
+
+/// A target covered by rulesets from more than one update channel
+#[derive(Debug, PartialEq, Clone)]
+pub struct Conflict {
+    pub target: String,
+    /// The names of the update channels providing rulesets for the target, sorted
+    pub channels: Vec<String>,
+}
+
+/// The parameters of the bloom filter stored for an update channel
+#[derive(Debug, PartialEq, Clone)]
+pub struct BloomStats {
+    pub name: String,
+    /// The number of bits in the bitmap
+    pub bitmap_bits: u64,
+    /// The number of hash functions
+    pub k_num: u32,
+    /// The number of bits of the bitmap which are set
+    pub bits_set: u64,
+    /// The estimated probability that a host not in the filter is reported as present, computed
+    /// from the fraction of bits set
+    pub false_positive_rate: f64,
+}
+
+/// The default maximum size, in bytes, of any single file downloaded from an update channel
+pub const DEFAULT_MAX_DOWNLOAD_BYTES: usize = 64 * 1024 * 1024;
+
+/// The default maximum size, in bytes, which a downloaded ruleset bundle may decompress to
+pub const DEFAULT_MAX_DECOMPRESSED_BYTES: usize = 256 * 1024 * 1024;
+
+/// The default maximum age, in seconds, of stored rulesets which are updated with a ruleset diff
+/// rather than the full bundle
+pub const DEFAULT_MAX_DIFF_AGE: usize = 30 * 86400;
 
 pub struct Updater {
     rulesets: ThreadSafeRuleSets,
@@ -55,6 +201,15 @@ pub struct Updater {
     storage: ThreadSafeStorage,
     default_rulesets: Option<String>,
     periodicity: usize,
+    max_download_bytes: usize,
+    max_decompressed_bytes: usize,
+    max_diff_age: usize,
+    fetcher: Box<dyn Fetcher>,
+    on_applied: Option<UpdatesAppliedCallback>,
+    jitter_fraction: f64,
+    jitter_seed: u64,
+    cancellation_token: Option<Arc<AtomicBool>>,
+    user_agent: Option<String>,
 }
 
 impl Updater {
@@ -63,28 +218,162 @@ impl Updater {
     ///
     /// # Arguments
     ///
-    /// * `rulesets` - A ruleset struct to update, wrapped in an Arc<Mutex>
+    /// * `rulesets` - A ruleset struct to update, shared as ThreadSafeRuleSets
     /// * `update_channels` - The update channels where to look for new rulesets
     /// * `storage` - The storage engine for key-value pairs, wrapped in an Arc<Mutex>
     /// * `default_rulesets` - An optional string representing the default rulesets, which may or
     /// may not be replaced by updates
     /// * `periodicity` - The interval to check for new rulesets
     pub fn new(rulesets: ThreadSafeRuleSets, update_channels: UpdateChannels, storage: ThreadSafeStorage, default_rulesets: Option<String>, periodicity: usize) -> Updater {
-        Updater {
-            rulesets,
-            blooms: Arc::new(Mutex::new(vec![])),
-            update_channels,
-            storage,
-            default_rulesets,
-            periodicity,
+        let mut builder = UpdaterBuilder::new(rulesets, update_channels, storage).periodicity(periodicity);
+        if let Some(default_rulesets) = default_rulesets {
+            builder = builder.default_rulesets(default_rulesets);
         }
+        builder.build()
+    }
+
+    /// Set the maximum size, in bytes, of any single file downloaded from an update channel.
+    /// Downloads exceeding this are aborted.  Defaults to `DEFAULT_MAX_DOWNLOAD_BYTES`
+    ///
+    /// # Arguments
+    ///
+    /// * `max_download_bytes` - The maximum download size in bytes
+    pub fn set_max_download_bytes(&mut self, max_download_bytes: usize) {
+        self.max_download_bytes = max_download_bytes;
+    }
+
+    /// Get the interval, in seconds, at which to check for new rulesets
+    pub fn get_periodicity(&self) -> usize {
+        self.periodicity
+    }
+
+    /// Set the interval, in seconds, at which to check for new rulesets.  This takes effect from
+    /// the next call to `time_to_next_check`, for instance to check less often on metered
+    /// connections
+    ///
+    /// # Arguments
+    ///
+    /// * `periodicity` - The interval to check for new rulesets
+    pub fn set_periodicity(&mut self, periodicity: usize) {
+        self.periodicity = periodicity;
+    }
+
+    /// Set the fraction of the periodicity by which the interval between checks is randomly
+    /// lengthened or shortened, so that clients which were installed or reset at the same time
+    /// don't all check the update server simultaneously.  For instance, `0.1` varies the interval
+    /// by up to 10% either way.  The fraction is clamped between 0 and 1, and defaults to 0
+    ///
+    /// # Arguments
+    ///
+    /// * `jitter_fraction` - The maximum fraction of the periodicity to vary the interval by
+    pub fn set_jitter_fraction(&mut self, jitter_fraction: f64) {
+        self.jitter_fraction = jitter_fraction.clamp(0.0, 1.0);
+    }
+
+    /// Set the seed from which the jitter of each interval is derived.  The jitter is otherwise
+    /// seeded randomly when the updater is created; a fixed seed makes it deterministic
+    ///
+    /// # Arguments
+    ///
+    /// * `jitter_seed` - The seed to derive the jitter from
+    pub fn set_jitter_seed(&mut self, jitter_seed: u64) {
+        self.jitter_seed = jitter_seed;
+    }
+
+    /// Set the maximum size, in bytes, which a downloaded ruleset bundle may decompress to.
+    /// Bundles exceeding this are rejected.  Defaults to `DEFAULT_MAX_DECOMPRESSED_BYTES`
+    ///
+    /// # Arguments
+    ///
+    /// * `max_decompressed_bytes` - The maximum decompressed size in bytes
+    pub fn set_max_decompressed_bytes(&mut self, max_decompressed_bytes: usize) {
+        self.max_decompressed_bytes = max_decompressed_bytes;
+    }
+
+    /// Set the maximum age, in seconds, of stored rulesets which are updated by fetching a diff
+    /// against the latest rulesets.  Older rulesets are replaced by the full bundle, as are any
+    /// for which no diff can be applied.  Defaults to `DEFAULT_MAX_DIFF_AGE`
+    ///
+    /// # Arguments
+    ///
+    /// * `max_diff_age` - The maximum age in seconds, relative to the latest rulesets
+    pub fn set_max_diff_age(&mut self, max_diff_age: usize) {
+        self.max_diff_age = max_diff_age;
+    }
+
+    /// Set the fetcher used to make network requests.  Defaults to `HttpReqFetcher`.  Requests are
+    /// made with the user agent of the updater, see `set_user_agent`
+    ///
+    /// # Arguments
+    ///
+    /// * `fetcher` - The fetcher to make requests with
+    pub fn set_fetcher(&mut self, fetcher: Box<dyn Fetcher>) {
+        self.fetcher = fetcher;
+    }
+
+    /// Get the User-Agent header sent with update requests
+    pub fn get_user_agent(&self) -> &str {
+        self.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT)
+    }
+
+    /// Set the User-Agent header sent with all update requests, for instance to identify the
+    /// extension version and platform to the update server.  `None` restores the default of
+    /// `DEFAULT_USER_AGENT`
+    ///
+    /// # Arguments
+    ///
+    /// * `user_agent` - The value of the User-Agent header
+    pub fn set_user_agent(&mut self, user_agent: Option<String>) {
+        self.user_agent = user_agent;
+    }
+
+    /// Set a token which cancels `perform_check` once it is set to `true`, for instance when the
+    /// application is shutting down.  The token is checked between update channels and before
+    /// each download.  It is not reset by the updater, so it must be set back to `false` before
+    /// checking again
+    ///
+    /// # Arguments
+    ///
+    /// * `cancellation_token` - The token to cancel checks with
+    pub fn set_cancellation_token(&mut self, cancellation_token: Arc<AtomicBool>) {
+        self.cancellation_token = Some(cancellation_token);
+    }
+
+    /// Returns whether the cancellation token has been set
+    fn is_cancelled(&self) -> bool {
+        self.cancellation_token.as_ref().is_some_and(|token| token.load(Ordering::SeqCst))
+    }
+
+    /// Register a callback which is invoked once each time stored updates have been applied, for
+    /// instance to clear caches derived from the rulesets.  The callback runs after the updater
+    /// has released its locks on the rulesets, bloom filters and storage, so it may lock them
+    /// itself without deadlocking
+    ///
+    /// # Arguments
+    ///
+    /// * `callback` - The function to call after updates are applied
+    pub fn on_applied(&mut self, callback: UpdatesAppliedCallback) {
+        self.on_applied = Some(callback);
     }
 
-    /// Get the current timestamp in seconds
+    /// Get the current timestamp in seconds.  See `timestamp_of` for the behavior when the system
+    /// clock is set before the Unix epoch
     fn current_timestamp() -> Timestamp {
-	let since_the_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-	let current_timestamp = since_the_epoch.as_secs();
-        current_timestamp as Timestamp
+        Self::timestamp_of(SystemTime::now())
+    }
+
+    /// Get the timestamp of the given time in seconds.  Times before the Unix epoch, such as those
+    /// reported by devices whose clocks have not yet been synchronized, fall back to 0 rather than
+    /// panicking.  Since any recorded `last-checked` time is then in the future,
+    /// `time_to_next_check` will report that a check is due immediately
+    fn timestamp_of(time: SystemTime) -> Timestamp {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(since_the_epoch) => since_the_epoch.as_secs() as Timestamp,
+            Err(_) => {
+                warn!("The system clock is set before the Unix epoch.  Using a timestamp of 0.");
+                0
+            }
+        }
     }
 
     /// Returns an `Option<i32>` optional timestamp if there are new updates.  If no new updates
@@ -94,18 +383,30 @@ impl Updater {
     ///
     /// * `uc` - The update channel to check for new updates on
     fn check_for_new_updates(&self, uc: &UpdateChannel) -> Option<Timestamp> {
-        let mut writer = Vec::new();
-
         let timestamp_str = match uc.format {
             UpdateChannelFormat::RuleSets => "/latest-rulesets-timestamp",
             UpdateChannelFormat::Bloom => "/latest-bloom-timestamp",
         };
-        let res = match request::get(uc.update_path_prefix.clone() + timestamp_str, &mut writer) { Ok(result) => result,
+        let etag_key = format!("uc-timestamp-etag: {}", &uc.name);
+        let last_modified_key = format!("uc-timestamp-last-modified: {}", &uc.name);
+        let validators = {
+            let storage = self.storage.lock().unwrap();
+            CacheValidators {
+                etag: storage.get_string(etag_key.clone()),
+                last_modified: storage.get_string(last_modified_key.clone()),
+            }
+        };
+
+        let (status_code, body, response_validators) = match self.get_conditional(&(uc.update_path_prefix.clone() + timestamp_str), &validators) {
+            Ok(result) => result,
             Err(_) => return None
         };
 
-        if res.status_code().is_success() {
-            let ts_string = match String::from_utf8(writer) {
+        if status_code == 304 {
+            channel_log!(debug, uc.name, action = "not_modified", "Timestamp not modified since last check");
+            None
+        } else if is_success(status_code) {
+            let ts_string = match String::from_utf8(body) {
                 Ok(timestamp) => timestamp,
                 Err(_) => return None
             };
@@ -115,11 +416,20 @@ impl Updater {
                 Err(_) => return None
             };
 
-            let stored_timestamp: Timestamp = self.storage.lock().unwrap().get_int(format!("uc-timestamp: {}", &uc.name)).unwrap_or(0);
+            let mut storage = self.storage.lock().unwrap();
+            let stored_timestamp: Timestamp = storage.get_int(format!("uc-timestamp: {}", &uc.name)).unwrap_or(0);
 
             if stored_timestamp < timestamp {
                 Some(timestamp)
             } else {
+                // Only remember the validators once the timestamp they describe has been applied,
+                // so that a failed update is retried on the next check
+                for (key, value) in [(etag_key, response_validators.etag), (last_modified_key, response_validators.last_modified)] {
+                    match value {
+                        Some(value) => storage.set_string(key, value),
+                        None => storage.remove(key),
+                    }
+                }
                 None
             }
         } else {
@@ -138,6 +448,103 @@ impl Updater {
         timestamps
     }
 
+    /// Returns when update channels were last checked, and the timestamps of the latest updates
+    /// stored and applied for each update channel.  All values are read under a single lock of
+    /// the storage, so that they are consistent with one another
+    pub fn get_channel_status(&self) -> UpdaterStatus {
+        let storage = self.storage.lock().unwrap();
+        let last_checked = storage.get_int(String::from("last-checked"));
+
+        let channels = self.update_channels.get_all().iter().map(|uc| {
+            (uc.name.clone(), ChannelStatus {
+                last_checked,
+                last_seen_timestamp: storage.get_int(format!("uc-timestamp: {}", &uc.name)),
+                last_applied_timestamp: storage.get_int(format!("uc-stored-timestamp: {}", &uc.name)),
+            })
+        }).collect();
+
+        UpdaterStatus {
+            last_checked,
+            channels,
+        }
+    }
+
+    /// Returns the rulesets JSON stored for the ruleset update channel with the given name,
+    /// decompressed, exactly as it would be applied.  Returns `None` if no such update channel is
+    /// configured, or no rulesets could be read from storage for it
+    ///
+    /// # Arguments
+    ///
+    /// * `channel_name` - The name of the update channel to get the stored rulesets JSON of
+    pub fn get_stored_rulesets_json(&self, channel_name: &str) -> Option<String> {
+        let uc = self.update_channels.get_all().iter()
+            .find(|uc| uc.name == channel_name && uc.format == UpdateChannelFormat::RuleSets)?;
+        self.load_stored_rulesets(uc).ok().flatten()
+    }
+
+    /// Check that the rulesets stored for each ruleset update channel parse, and that the
+    /// timestamp within them matches the stored timestamp, to diagnose partially written or
+    /// otherwise corrupted storage.  Returns the status of each update channel alongside its name
+    pub fn verify_stored_integrity(&self) -> Vec<(String, IntegrityStatus)> {
+        self.update_channels.get_all().iter().filter(|uc| uc.format == UpdateChannelFormat::RuleSets).map(|uc| {
+            let stored_timestamp = self.storage.lock().unwrap().get_int(format!("uc-stored-timestamp: {}", &uc.name));
+            let status = match (self.load_stored_rulesets(uc), stored_timestamp) {
+                (Err(err), _) => IntegrityStatus::Corrupt(err.to_string()),
+                (Ok(None), None) => IntegrityStatus::NotStored,
+                (Ok(None), Some(_)) | (Ok(Some(_)), None) => IntegrityStatus::Incomplete,
+                (Ok(Some(rulesets_json_string)), Some(stored_timestamp)) => {
+                    match serde_json::from_str::<Value>(&rulesets_json_string) {
+                        Ok(rulesets_json_value) => match Self::check_json_timestamp(uc, &rulesets_json_value, "timestamp", stored_timestamp) {
+                            Ok(()) => IntegrityStatus::Valid,
+                            Err(UpdaterError::TimestampMismatch { .. }) => IntegrityStatus::TimestampMismatch,
+                            Err(err) => IntegrityStatus::Corrupt(err.to_string()),
+                        },
+                        Err(err) => IntegrityStatus::Corrupt(err.to_string()),
+                    }
+                }
+            };
+            (uc.name.clone(), status)
+        }).collect()
+    }
+
+    /// Report the size, number of hash functions and estimated false positive rate of the bloom
+    /// filter stored for each bloom filter update channel, for tuning and diagnostics.  Update
+    /// channels without a stored bloom filter are left out
+    pub fn bloom_stats(&self) -> Vec<BloomStats> {
+        let storage = self.storage.lock().unwrap();
+        self.update_channels.get_all().iter().filter(|uc| uc.format == UpdateChannelFormat::Bloom).filter_map(|uc| {
+            let bloom = storage.get_bytes(format!("bloom: {}", &uc.name))?;
+            let bitmap_bits = storage.get_int(format!("bloom_bitmap_bits: {}", &uc.name))? as u64;
+            let k_num = storage.get_int(format!("bloom_k_num: {}", &uc.name))? as u32;
+
+            let bits_set = count_bits_set(&bloom, bitmap_bits);
+            let false_positive_rate = if bitmap_bits == 0 {
+                1.0
+            } else {
+                (bits_set as f64 / bitmap_bits as f64).powi(k_num as i32)
+            };
+            Some(BloomStats { name: uc.name.clone(), bitmap_bits, k_num, bits_set, false_positive_rate })
+        }).collect()
+    }
+
+    /// Report the targets of the applied rulesets which are covered by rulesets from more than
+    /// one update channel, since which of them rewrites a URL depends on the order they were
+    /// added in.  Rulesets which were not added from an update channel, such as the default
+    /// rulesets, are not considered.  This does not change how URLs are rewritten
+    pub fn detect_conflicts(&self) -> Vec<Conflict> {
+        let rulesets = shared::load(&self.rulesets);
+        rulesets.iter().filter_map(|(target, rs_vec)| {
+            let channels: BTreeSet<&str> = rs_vec.iter().filter_map(|ruleset| ruleset.source_channel()).collect();
+            if channels.len() < 2 {
+                return None;
+            }
+
+            let channels: Vec<String> = channels.into_iter().map(String::from).collect();
+            warn!("{}: Covered by rulesets from several update channels: {}", target, channels.join(", "));
+            Some(Conflict { target: target.to_string(), channels })
+        }).collect()
+    }
+
     /// Given an update channel and timestamp, this returns a result-wrapped tuple, the first value the first value is
     /// a `Vec<u8>` of the signature file, the second is a `Vec<u8>` of the rulesets file.
     ///
@@ -150,22 +557,29 @@ impl Updater {
 
         // TODO: Use futures to asynchronously fetch signature and rulesets
 
-        let mut signature_writer = Vec::new();
-        let signature_res = request::get(update_channel.update_path_prefix.clone() + "/rulesets-signature." + &rulesets_timestamp.to_string() + ".sha256", &mut signature_writer)?;
-
-        if !signature_res.status_code().is_success() {
-            return Err(Box::new(UpdaterError::new(format!("{}: A non-2XX response was returned from the ruleset signature URL", &update_channel.name))));
-        }
+        let signature = self.download(update_channel, &format!("/rulesets-signature.{}.sha256", rulesets_timestamp), "ruleset signature")?;
+        let rulesets = self.download(update_channel, &format!("/default.rulesets.{}.gz", rulesets_timestamp), "ruleset")?;
 
+        Ok((signature, rulesets))
+    }
 
-        let mut rulesets_writer = Vec::new();
-        let rulesets_res = request::get(update_channel.update_path_prefix.clone() + "/default.rulesets." + &rulesets_timestamp.to_string() + ".gz", &mut rulesets_writer)?;
+    /// Given an update channel and the timestamps of the stored and latest rulesets, this returns a
+    /// result-wrapped tuple, the first value is a `Vec<u8>` of the signature file, the second is a
+    /// `Vec<u8>` of the diff between the two bundles.  Update channels only publish diffs from
+    /// recent bundles, so this fails for stored rulesets which are too old
+    ///
+    /// # Arguments
+    ///
+    /// * `from_timestamp` - The timestamp of the stored rulesets
+    /// * `to_timestamp` - The timestamp of the latest rulesets
+    /// * `update_channel` - The update channel to download the diff for
+    fn get_rulesets_diff(&self, from_timestamp: Timestamp, to_timestamp: Timestamp, update_channel: &UpdateChannel) -> Result<(Vec<u8>, Vec<u8>), Box<dyn Error>> {
+        self.storage.lock().unwrap().set_int(format!("uc-timestamp: {}", &update_channel.name), to_timestamp);
 
-        if !rulesets_res.status_code().is_success() {
-            return Err(Box::new(UpdaterError::new(format!("{}: A non-2XX response was returned from the ruleset URL", &update_channel.name))));
-        }
+        let signature = self.download(update_channel, &format!("/rulesets-diff-signature.{}-{}.sha256", from_timestamp, to_timestamp), "ruleset diff signature")?;
+        let diff = self.download(update_channel, &format!("/rulesets-diff.{}-{}.json", from_timestamp, to_timestamp), "ruleset diff")?;
 
-        Ok((signature_writer, rulesets_writer))
+        Ok((signature, diff))
     }
 
     /// Given an update channel and timestamp, this returns a result-wrapped tuple, the first value the first value is
@@ -181,30 +595,83 @@ impl Updater {
 
         // TODO: Use futures to asynchronously fetch signature and rulesets
 
-        let mut signature_writer = Vec::new();
-        let signature_res = request::get(update_channel.update_path_prefix.clone() + "/bloom-signature." + &bloom_timestamp.to_string() + ".sha256", &mut signature_writer)?;
+        let signature = self.download(update_channel, &format!("/bloom-signature.{}.sha256", bloom_timestamp), "bloom signature")?;
+        let bloom_metadata = self.download(update_channel, &format!("/bloom-metadata.{}.json", bloom_timestamp), "bloom metadata")?;
+        let bloom = self.download(update_channel, &format!("/bloom.{}.bin", bloom_timestamp), "bloom")?;
 
-        if !signature_res.status_code().is_success() {
-            return Err(Box::new(UpdaterError::new(format!("{}: A non-2XX response was returned from the bloom signature URL", &update_channel.name))));
-        }
+        Ok((signature, bloom_metadata, bloom))
+    }
 
+    /// Perform a GET request for the given URL with the fetcher
+    fn get(&self, url: &str) -> Result<(u16, Vec<u8>), Box<dyn Error>> {
+        self.fetcher.get_with_user_agent(url, self.max_download_bytes, self.get_user_agent())
+    }
 
-        let mut bloom_metadata_writer = Vec::new();
-        let bloom_metadata_res = request::get(update_channel.update_path_prefix.clone() + "/bloom-metadata." + &bloom_timestamp.to_string() + ".json", &mut bloom_metadata_writer)?;
+    /// Perform a conditional GET request for the given URL with the fetcher
+    fn get_conditional(&self, url: &str, validators: &CacheValidators) -> Result<(u16, Vec<u8>, CacheValidators), Box<dyn Error>> {
+        self.fetcher.get_conditional_with_user_agent(url, self.max_download_bytes, validators, self.get_user_agent())
+    }
 
-        if !bloom_metadata_res.status_code().is_success() {
-            return Err(Box::new(UpdaterError::new(format!("{}: A non-2XX response was returned from the bloom metadata URL", &update_channel.name))));
+    /// Download a file from the given update channel, returning its contents.  Fails if a non-2XX
+    /// response is returned, or if the response exceeds the maximum download size
+    ///
+    /// # Arguments
+    ///
+    /// * `update_channel` - The update channel to download from
+    /// * `path` - The path of the file, relative to the update path prefix of the channel
+    /// * `description` - A description of the file, for error messages
+    fn download(&self, update_channel: &UpdateChannel, path: &str, description: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        if self.is_cancelled() {
+            return Err(Box::new(UpdaterError::Cancelled { name: update_channel.name.clone(), file: description.to_string() }));
         }
 
+        let (status_code, body) = match self.get(&(update_channel.update_path_prefix.clone() + path)) {
+            Ok(result) => result,
+            Err(err) => return Err(Box::new(UpdaterError::NetworkError { name: update_channel.name.clone(), file: description.to_string(), message: err.to_string() }))
+        };
 
-        let mut bloom_writer = Vec::new();
-        let bloom_res = request::get(update_channel.update_path_prefix.clone() + "/bloom." + &bloom_timestamp.to_string() + ".bin", &mut bloom_writer)?;
+        if body.len() > self.max_download_bytes {
+            return Err(Box::new(UpdaterError::DownloadTooLarge { name: update_channel.name.clone(), file: description.to_string(), max_bytes: self.max_download_bytes }));
+        }
 
-        if !bloom_res.status_code().is_success() {
-            return Err(Box::new(UpdaterError::new(format!("{}: A non-2XX response was returned from the bloom URL", &update_channel.name))));
+        if !is_success(status_code) {
+            return Err(Box::new(UpdaterError::BadStatus { name: update_channel.name.clone(), file: description.to_string(), code: status_code }));
         }
 
-        Ok((signature_writer, bloom_metadata_writer, bloom_writer))
+        Ok(body)
+    }
+
+    /// Returns whether the given signature of the given data verifies with any of the keys stored
+    /// in the given update channel, using the signature algorithm of the update channel.  If the
+    /// keys were taken from a certificate chain, returns an error rather than verifying once any
+    /// certificate of the chain is no longer valid
+    ///
+    /// # Arguments
+    ///
+    /// * `update_channel` - Contains the keys and algorithm which we verify the signature with
+    /// * `signature` - The signature to verify
+    /// * `data` - The data which has been signed
+    fn verify_signature(update_channel: &UpdateChannel, signature: &[u8], data: &[u8]) -> Result<bool, Box<dyn Error>> {
+        if update_channel.has_invalid_certificates() {
+            return Err(Box::new(UpdaterError::CertificateExpired { name: update_channel.name.clone() }));
+        }
+        // A key which fails to verify the signature, for instance a rotated key of a different
+        // size, is treated as not verifying it, so that the remaining keys are still tried
+        let verifies_with = |key: &PKey<Public>| -> Result<bool, ErrorStack> {
+            match update_channel.signature_algorithm {
+                SignatureAlgorithm::RsaPssSha256 => {
+                    let mut verifier = Verifier::new(MessageDigest::sha256(), key)?;
+                    verifier.set_rsa_padding(Padding::PKCS1_PSS)?;
+                    verifier.update(data)?;
+                    verifier.verify(signature)
+                },
+                SignatureAlgorithm::Ed25519 => {
+                    let mut verifier = Verifier::new_without_digest(key)?;
+                    verifier.verify_oneshot(signature, data)
+                },
+            }
+        };
+        Ok(update_channel.keys.iter().any(|key| verifies_with(key).unwrap_or(false)))
     }
 
     /// If the given signature for the given rulesets verifies with the key stored in the given
@@ -219,103 +686,252 @@ impl Updater {
     /// matches the timestamp in the signed rulesets JSON
     /// * `update_channel` - Contains the key which we verify the signatures with
     fn verify_and_store_new_rulesets(&self, signature: Vec<u8>, rulesets: Vec<u8>, rulesets_timestamp: Timestamp, update_channel: &UpdateChannel) -> Result<(), Box<dyn Error>> {
-        let update_channel_key = PKey::from_rsa(update_channel.key.clone())?;
-        let mut verifier = Verifier::new(MessageDigest::sha256(), &update_channel_key)?;
-        verifier.set_rsa_padding(Padding::PKCS1_PSS)?;
+        if Self::verify_signature(update_channel, &signature, &rulesets)? {
+            channel_log!(info, update_channel.name, action = "store", timestamp = rulesets_timestamp, "Downloaded ruleset signature checks out.  Storing rulesets.");
 
-        verifier.update(&rulesets)?;
+            let rulesets_json_string = self.decompress_rulesets(&rulesets, rulesets_timestamp, update_channel)?;
+            let rulesets_json_value: Value = serde_json::from_str(&rulesets_json_string)?;
+            Self::check_json_timestamp(update_channel, &rulesets_json_value, "timestamp", rulesets_timestamp)?;
 
-        if verifier.verify(&signature)? {
-            info!("{}: Downloaded ruleset signature checks out.  Storing rulesets.", update_channel.name);
+            // The bundle has been validated, so store it as downloaded rather than recompressing it
+            self.store_compressed_rulesets(rulesets, update_channel);
+        } else {
+            return Err(Box::new(UpdaterError::SignatureInvalid { name: update_channel.name.clone() }));
+        }
 
-            let mut rulesets_json_string = String::new();
-            let mut decoder = GzDecoder::new(&rulesets[..]);
-            decoder.read_to_string(&mut rulesets_json_string)?;
+        Ok(())
+    }
 
-            let rulesets_json_value: Value = serde_json::from_str(&rulesets_json_string)?;
-            match rulesets_json_value.get("timestamp") {
-                Some(Value::Number(json_timestamp)) if json_timestamp.is_i64() => {
-                    if json_timestamp.as_i64().unwrap() != rulesets_timestamp as i64 {
-                        return Err(Box::new(UpdaterError::new(format!("{}: JSON timestamp does not match with latest timestamp file", &update_channel.name))));
-                    }
-                },
-                _ => {
-                    return Err(Box::new(UpdaterError::new(format!("{}: Could not parse JSON `timestamp`", &update_channel.name))));
+    /// Check that the given field of a JSON object is the given timestamp
+    ///
+    /// # Arguments
+    ///
+    /// * `update_channel` - The update channel the JSON was published on, for error messages
+    /// * `json_value` - The JSON object containing the timestamp
+    /// * `field` - The name of the timestamp field
+    /// * `timestamp` - The expected timestamp
+    fn check_json_timestamp(update_channel: &UpdateChannel, json_value: &Value, field: &str, timestamp: Timestamp) -> Result<(), UpdaterError> {
+        match json_value.get(field) {
+            Some(Value::Number(json_timestamp)) if json_timestamp.is_i64() => {
+                if json_timestamp.as_i64().unwrap() != timestamp as i64 {
+                    return Err(UpdaterError::TimestampMismatch { name: update_channel.name.clone() });
                 }
-            }
+                Ok(())
+            },
+            _ => Err(UpdaterError::MetadataParse { name: update_channel.name.clone(), field: field.to_string() })
+        }
+    }
 
-            self.storage.lock().unwrap().set_string(format!("rulesets: {}", update_channel.name), rulesets_json_string);
-        } else {
-            return Err(Box::new(UpdaterError::new(format!("{}: Downloaded ruleset signature is invalid.  Aborting.", &update_channel.name))));
+    /// Decompress a gzipped rulesets JSON string, failing if it decompresses to more than the
+    /// maximum decompressed size
+    ///
+    /// # Arguments
+    ///
+    /// * `rulesets` - The gzipped rulesets JSON
+    /// * `rulesets_timestamp` - The timestamp of the rulesets, for error messages
+    /// * `update_channel` - The update channel the rulesets were published on
+    fn decompress_rulesets(&self, rulesets: &[u8], rulesets_timestamp: Timestamp, update_channel: &UpdateChannel) -> Result<String, Box<dyn Error>> {
+        let mut rulesets_json_string = String::new();
+        let decoder = GzDecoder::new(rulesets);
+        if let Err(err) = decoder.take(self.max_decompressed_bytes as u64 + 1).read_to_string(&mut rulesets_json_string) {
+            return Err(Box::new(UpdaterError::DecompressFailed {
+                name: update_channel.name.clone(),
+                timestamp: rulesets_timestamp,
+                truncated: err.kind() == io::ErrorKind::UnexpectedEof,
+            }));
         }
+        if rulesets_json_string.len() > self.max_decompressed_bytes {
+            return Err(Box::new(UpdaterError::DecompressedTooLarge { name: update_channel.name.clone(), max_bytes: self.max_decompressed_bytes }));
+        }
+        Ok(rulesets_json_string)
+    }
 
-        Ok(())
+    /// Store gzipped rulesets JSON for the update channel, removing any rulesets JSON stored
+    /// uncompressed by earlier versions
+    fn store_compressed_rulesets(&self, rulesets: Vec<u8>, update_channel: &UpdateChannel) {
+        let mut storage = self.storage.lock().unwrap();
+        storage.set_bytes(format!("rulesets-gz: {}", update_channel.name), rulesets);
+        storage.remove(format!("rulesets: {}", update_channel.name));
     }
 
-    fn verify_and_store_new_bloom(&self, signature: Vec<u8>, bloom_metadata: Vec<u8>, bloom: Vec<u8>, bloom_timestamp: Timestamp, update_channel: &UpdateChannel) -> Result<(), Box<dyn Error>> {
-        let update_channel_key = PKey::from_rsa(update_channel.key.clone())?;
-        let mut verifier = Verifier::new(MessageDigest::sha256(), &update_channel_key)?;
-        verifier.set_rsa_padding(Padding::PKCS1_PSS)?;
+    /// Load the rulesets JSON stored for the update channel.  Rulesets are stored gzipped, but
+    /// rulesets stored uncompressed by earlier versions are still read.  Returns `None` if no
+    /// rulesets are stored
+    ///
+    /// # Arguments
+    ///
+    /// * `update_channel` - The update channel to load the stored rulesets of
+    fn load_stored_rulesets(&self, update_channel: &UpdateChannel) -> Result<Option<String>, Box<dyn Error>> {
+        let (compressed, uncompressed, stored_timestamp) = {
+            let storage = self.storage.lock().unwrap();
+            (storage.get_bytes(format!("rulesets-gz: {}", update_channel.name)),
+             storage.get_string(format!("rulesets: {}", update_channel.name)),
+             storage.get_int(format!("uc-stored-timestamp: {}", update_channel.name)).unwrap_or(0))
+        };
+
+        match compressed {
+            Some(compressed) => Ok(Some(self.decompress_rulesets(&compressed, stored_timestamp, update_channel)?)),
+            None => Ok(uncompressed)
+        }
+    }
+
+    /// If the given signature for the given diff verifies with the key stored in the given update
+    /// channel, apply the diff to the rulesets stored for this update channel.  A diff is a JSON
+    /// object of the form `{"from": <timestamp>, "to": <timestamp>, "add": [<rulesets>], "remove":
+    /// [<ruleset names>]}`, where an added ruleset replaces any stored ruleset of the same name.
+    /// Returns a result-wrapped unit
+    ///
+    /// # Arguments
+    ///
+    /// * `signature` - The signature of the diff
+    /// * `diff` - The diff to check the signature for and apply
+    /// * `from_timestamp` - The timestamp of the stored rulesets, which must match the `from`
+    ///   timestamp in the signed diff JSON
+    /// * `to_timestamp` - The timestamp of the latest rulesets, which must match the `to` timestamp
+    ///   in the signed diff JSON
+    /// * `update_channel` - Contains the key which we verify the signatures with
+    fn verify_and_apply_rulesets_diff(&self, signature: Vec<u8>, diff: Vec<u8>, from_timestamp: Timestamp, to_timestamp: Timestamp, update_channel: &UpdateChannel) -> Result<(), Box<dyn Error>> {
+        if !Self::verify_signature(update_channel, &signature, &diff)? {
+            return Err(Box::new(UpdaterError::SignatureInvalid { name: update_channel.name.clone() }));
+        }
+        channel_log!(info, update_channel.name, action = "apply_diff", timestamp = to_timestamp, "Downloaded ruleset diff signature checks out.  Applying diff to stored rulesets.");
 
-        verifier.update(&bloom_metadata)?;
+        let metadata_parse = |field: &str| -> Box<dyn Error> {
+            Box::new(UpdaterError::MetadataParse { name: update_channel.name.clone(), field: field.to_string() })
+        };
 
-        if verifier.verify(&signature)? {
-            info!("{}: Bloom metadata signature checks out.", update_channel.name);
+        let diff_json_value: Value = serde_json::from_slice(&diff)?;
+        Self::check_json_timestamp(update_channel, &diff_json_value, "from", from_timestamp)?;
+        Self::check_json_timestamp(update_channel, &diff_json_value, "to", to_timestamp)?;
 
-            let metadata_json_value: Value = serde_json::from_slice(&bloom_metadata)?;
-            match metadata_json_value.get("timestamp") {
-                Some(Value::Number(json_timestamp)) if json_timestamp.is_i64() => {
-                    if json_timestamp.as_i64().unwrap() != bloom_timestamp as i64 {
-                        return Err(Box::new(UpdaterError::new(format!("{}: JSON timestamp does not match with latest timestamp file", &update_channel.name))));
+        let added = match diff_json_value.get("add") {
+            Some(Value::Array(added)) => added.clone(),
+            None => vec![],
+            _ => return Err(metadata_parse("add"))
+        };
+        let mut removed_names = vec![];
+        match diff_json_value.get("remove") {
+            Some(Value::Array(removed)) => {
+                for name in removed {
+                    match name.as_str() {
+                        Some(name) => removed_names.push(name.to_string()),
+                        None => return Err(metadata_parse("remove"))
                     }
-                },
-                _ => {
-                    return Err(Box::new(UpdaterError::new(format!("{}: Could not parse JSON `timestamp`", &update_channel.name))));
                 }
+            },
+            None => {},
+            _ => return Err(metadata_parse("remove"))
+        }
+        for ruleset in &added {
+            match ruleset.get("name").and_then(Value::as_str) {
+                Some(name) => removed_names.push(name.to_string()),
+                None => return Err(metadata_parse("add"))
+            }
+        }
+
+        let stored_rulesets_json_string = match self.load_stored_rulesets(update_channel)? {
+            Some(stored_rulesets_json_string) => stored_rulesets_json_string,
+            None => return Err(Box::new(UpdaterError::MissingStoredUpdate { name: update_channel.name.clone() }))
+        };
+        let mut rulesets_json_value: Value = serde_json::from_str(&stored_rulesets_json_string)?;
+        Self::check_json_timestamp(update_channel, &rulesets_json_value, "timestamp", from_timestamp)?;
+
+        match rulesets_json_value.get_mut("rulesets") {
+            Some(Value::Array(rulesets)) => {
+                rulesets.retain(|ruleset| {
+                    match ruleset.get("name").and_then(Value::as_str) {
+                        Some(name) => !removed_names.iter().any(|removed_name| removed_name == name),
+                        None => true
+                    }
+                });
+                rulesets.extend(added);
+            },
+            _ => return Err(metadata_parse("rulesets"))
+        }
+        rulesets_json_value["timestamp"] = Value::from(to_timestamp);
 
+        let rulesets_json_string = serde_json::to_string(&rulesets_json_value)?;
+        if rulesets_json_string.len() > self.max_decompressed_bytes {
+            return Err(Box::new(UpdaterError::DecompressedTooLarge { name: update_channel.name.clone(), max_bytes: self.max_decompressed_bytes }));
+        }
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(rulesets_json_string.as_bytes())?;
+        self.store_compressed_rulesets(encoder.finish()?, update_channel);
+        Ok(())
+    }
+
+    /// Download, verify and store the rulesets with the given timestamp for the given update
+    /// channel.  If rulesets have previously been stored for the update channel, a diff from them
+    /// is tried first, falling back to the full bundle if no diff could be applied
+    ///
+    /// # Arguments
+    ///
+    /// * `rulesets_timestamp` - The timestamp for the rulesets
+    /// * `update_channel` - The update channel to download rulesets for
+    fn fetch_and_store_rulesets(&self, rulesets_timestamp: Timestamp, update_channel: &UpdateChannel) -> Result<(), Box<dyn Error>> {
+        let stored_timestamp = self.storage.lock().unwrap().get_int(format!("uc-stored-timestamp: {}", update_channel.name));
+        if let Some(stored_timestamp) = stored_timestamp.filter(|stored_timestamp| rulesets_timestamp.saturating_sub(*stored_timestamp) <= self.max_diff_age) {
+            let diff_result = self.get_rulesets_diff(stored_timestamp, rulesets_timestamp, update_channel)
+                .and_then(|(signature, diff)| self.verify_and_apply_rulesets_diff(signature, diff, stored_timestamp, rulesets_timestamp, update_channel));
+            match diff_result {
+                Ok(()) => return Ok(()),
+                Err(err) => channel_log!(info, update_channel.name, action = "download", timestamp = rulesets_timestamp, "Could not apply a ruleset diff, downloading the full bundle instead: {}", err)
             }
+        }
+
+        let (signature, rulesets) = self.get_new_rulesets(rulesets_timestamp, update_channel)?;
+        self.verify_and_store_new_rulesets(signature, rulesets, rulesets_timestamp, update_channel)
+    }
+
+    fn verify_and_store_new_bloom(&self, signature: Vec<u8>, bloom_metadata: Vec<u8>, bloom: Vec<u8>, bloom_timestamp: Timestamp, update_channel: &UpdateChannel) -> Result<(), Box<dyn Error>> {
+        if Self::verify_signature(update_channel, &signature, &bloom_metadata)? {
+            channel_log!(info, update_channel.name, action = "store", timestamp = bloom_timestamp, "Bloom metadata signature checks out.");
+
+            let metadata_json_value: Value = serde_json::from_slice(&bloom_metadata)?;
+            Self::check_json_timestamp(update_channel, &metadata_json_value, "timestamp", bloom_timestamp)?;
 
             let sha256sum: Vec<u8> = match metadata_json_value.get("sha256sum") {
                 Some(Value::String(sha256sum)) => {
                     match test::from_hex(sha256sum) {
                         Ok(sha256sum) => sha256sum,
                         Err(_) => {
-                            return Err(Box::new(UpdaterError::new(format!("{}: `sha256sum` is not formatted correctly", &update_channel.name))));
+                            return Err(Box::new(UpdaterError::MetadataParse { name: update_channel.name.clone(), field: String::from("sha256sum") }));
                         },
                     }
                 },
                 _ => {
-                    return Err(Box::new(UpdaterError::new(format!("{}: Could not parse JSON `sha256sum`", &update_channel.name))));
+                    return Err(Box::new(UpdaterError::MetadataParse { name: update_channel.name.clone(), field: String::from("sha256sum") }));
                 },
             };
             if sha256sum != digest::digest(&digest::SHA256, &bloom).as_ref() {
-                return Err(Box::new(UpdaterError::new(format!("{}: sha256sum of the bloom filter is invalid.  Aborting.", &update_channel.name))));
+                return Err(Box::new(UpdaterError::ChecksumMismatch { name: update_channel.name.clone() }));
             }
 
             let bitmap_bits: u64 = match metadata_json_value.get("bitmap_bits") {
                 Some(Value::Number(bitmap_bits)) if bitmap_bits.is_u64() => bitmap_bits.as_u64().unwrap(),
                 _ => {
-                    return Err(Box::new(UpdaterError::new(format!("{}: Could not parse JSON `bitmap_bits`", &update_channel.name))));
+                    return Err(Box::new(UpdaterError::MetadataParse { name: update_channel.name.clone(), field: String::from("bitmap_bits") }));
                 },
             };
 
             let k_num: u32 = match metadata_json_value.get("k_num") {
                 Some(Value::Number(k_num)) if k_num.is_u64() => k_num.as_u64().unwrap() as u32,
                 _ => {
-                    return Err(Box::new(UpdaterError::new(format!("{}: Could not parse JSON `k_num`", &update_channel.name))));
+                    return Err(Box::new(UpdaterError::MetadataParse { name: update_channel.name.clone(), field: String::from("k_num") }));
                 },
             };
 
             let sip_keys = match metadata_json_value.get("sip_keys") {
                 Some(Value::Array(sip_keys)) => sip_keys,
                 _ => {
-                    return Err(Box::new(UpdaterError::new(format!("{}: Could not parse JSON `sip_keys`", &update_channel.name))));
+                    return Err(Box::new(UpdaterError::MetadataParse { name: update_channel.name.clone(), field: String::from("sip_keys") }));
                 },
             };
             let sip_keys_0 = match &sip_keys[0] {
                 Value::Array(sip_keys_0) => sip_keys_0,
                 _ => {
-                    return Err(Box::new(UpdaterError::new(format!("{}: `sip_keys[0]` is not a JSON array", &update_channel.name))));
+                    return Err(Box::new(UpdaterError::MetadataParse { name: update_channel.name.clone(), field: String::from("sip_keys[0]") }));
                 },
             };
             let (sip_keys_0_0, sip_keys_0_1) = match sip_keys_0.as_slice() {
@@ -323,13 +939,13 @@ impl Updater {
                     (sip_keys_0_0.parse::<u64>().unwrap(), sip_keys_0_1.parse::<u64>().unwrap())
                 }
                 _ => {
-                    return Err(Box::new(UpdaterError::new(format!("{}: `sip_keys[0]` is not in the format [String(Number), String(Number)]", &update_channel.name))));
+                    return Err(Box::new(UpdaterError::MetadataParse { name: update_channel.name.clone(), field: String::from("sip_keys[0]") }));
                 },
             };
             let sip_keys_1 = match &sip_keys[1] {
                 Value::Array(sip_keys_1) => sip_keys_1,
                 _ => {
-                    return Err(Box::new(UpdaterError::new(format!("{}: `sip_keys[0]` is not a JSON array", &update_channel.name))));
+                    return Err(Box::new(UpdaterError::MetadataParse { name: update_channel.name.clone(), field: String::from("sip_keys[1]") }));
                 },
             };
             let (sip_keys_1_0, sip_keys_1_1) = match sip_keys_1.as_slice() {
@@ -337,18 +953,21 @@ impl Updater {
                     (sip_keys_1_0.parse::<u64>().unwrap(), sip_keys_1_1.parse::<u64>().unwrap())
                 }
                 _ => {
-                    return Err(Box::new(UpdaterError::new(format!("{}: `sip_keys[0]` is not in the format [String(Number), String(Number)]", &update_channel.name))));
+                    return Err(Box::new(UpdaterError::MetadataParse { name: update_channel.name.clone(), field: String::from("sip_keys[1]") }));
                 },
             };
 
-            let mut storage = self.storage.lock().unwrap();
-            storage.set_bytes(format!("bloom: {}", update_channel.name), bloom);
-            storage.set_int(format!("bloom_bitmap_bits: {}", update_channel.name), bitmap_bits as usize);
-            storage.set_int(format!("bloom_k_num: {}", update_channel.name), k_num as usize);
-            storage.set_int(format!("bloom_sip_keys_0_0: {}", update_channel.name), sip_keys_0_0 as usize);
-            storage.set_int(format!("bloom_sip_keys_0_1: {}", update_channel.name), sip_keys_0_1 as usize);
-            storage.set_int(format!("bloom_sip_keys_1_0: {}", update_channel.name), sip_keys_1_0 as usize);
-            storage.set_int(format!("bloom_sip_keys_1_1: {}", update_channel.name), sip_keys_1_1 as usize);
+            self.storage.lock().unwrap().set_batch(vec![
+                (format!("bloom: {}", update_channel.name), StorageValue::Bytes(bloom)),
+                (format!("bloom_bitmap_bits: {}", update_channel.name), StorageValue::Int(bitmap_bits as usize)),
+                (format!("bloom_k_num: {}", update_channel.name), StorageValue::Int(k_num as usize)),
+                (format!("bloom_sip_keys_0_0: {}", update_channel.name), StorageValue::Int(sip_keys_0_0 as usize)),
+                (format!("bloom_sip_keys_0_1: {}", update_channel.name), StorageValue::Int(sip_keys_0_1 as usize)),
+                (format!("bloom_sip_keys_1_0: {}", update_channel.name), StorageValue::Int(sip_keys_1_0 as usize)),
+                (format!("bloom_sip_keys_1_1: {}", update_channel.name), StorageValue::Int(sip_keys_1_1 as usize)),
+            ]);
+        } else {
+            return Err(Box::new(UpdaterError::SignatureInvalid { name: update_channel.name.clone() }));
         }
         Ok(())
 
@@ -361,118 +980,221 @@ impl Updater {
     /// 2. If new rulesets exist, download them along with a signature
     /// 3. Verify if the signature is valid, and if so...
     /// 4. Store the rulesets
-    pub fn perform_check(&mut self) {
+    ///
+    /// Bloom filter update channels are checked in the same way.  Returns what happened to each
+    /// update channel, in the order they were checked.
+    ///
+    /// If the cancellation token (see `set_cancellation_token`) is set, the check stops before the
+    /// next update channel or download.  The update channel being updated is reported as
+    /// `Cancelled` and later update channels are not reported.  Updates stored before the
+    /// cancellation are still applied, while the interrupted update and the check itself are
+    /// forgotten so that they are retried by the next check
+    pub fn perform_check(&mut self) -> Vec<UpdateChannelResult> {
         info!("Checking for new updates.");
 
+        let mut results = vec![];
+
+	let last_checked = self.storage.lock().unwrap().get_int(String::from("last-checked"));
 	self.storage.lock().unwrap().set_int(String::from("last-checked"), Self::current_timestamp());
 
 	let extension_timestamp = self.storage.lock().unwrap().get_int(String::from("extension-timestamp")).unwrap_or(0);
 
         let mut some_updated = false;
-        for uc in self.update_channels.get_all().iter().filter(|uc| uc.format == UpdateChannelFormat::RuleSets) {
-            if let Some(new_rulesets_timestamp) = self.check_for_new_updates(uc) {
-                if uc.replaces_default_rulesets && extension_timestamp > new_rulesets_timestamp {
-                    info!("{}: A new ruleset bundle has been released, but it is older than the extension-bundled rulesets it replaces.  Skipping.", uc.name);
-                    continue;
-                }
-                info!("{}: A new ruleset bundle has been released.  Downloading now.", uc.name);
+        let mut cancelled = false;
+        let update_channels = self.update_channels.get_all().iter().filter(|uc| uc.format == UpdateChannelFormat::RuleSets)
+            .chain(self.update_channels.get_all().iter().filter(|uc| uc.format == UpdateChannelFormat::Bloom));
+        for uc in update_channels {
+            if self.is_cancelled() {
+                cancelled = true;
+                break;
+            }
 
-                let (signature, rulesets) = match self.get_new_rulesets(new_rulesets_timestamp, uc) {
-                    Ok(rs_tuple) => rs_tuple,
-                    Err(err) => {
-                        error!("{:?}", err);
-                        continue;
-                    }
-                };
+            let seen_timestamp = self.storage.lock().unwrap().get_int(format!("uc-timestamp: {}", uc.name));
+            let result = match uc.format {
+                UpdateChannelFormat::RuleSets => self.check_rulesets_channel(uc, extension_timestamp),
+                UpdateChannelFormat::Bloom => self.check_bloom_channel(uc),
+            };
 
-                if let Err(err) = self.verify_and_store_new_rulesets(signature, rulesets, new_rulesets_timestamp, uc) {
-                    error!("{:?}", err);
-                    continue;
+            match result.status {
+                UpdateChannelStatus::Updated(_) => some_updated = true,
+                UpdateChannelStatus::Cancelled => {
+                    channel_log!(info, uc.name, action = "cancel", "Check cancelled.");
+                    self.restore_int(format!("uc-timestamp: {}", uc.name), seen_timestamp);
+                    cancelled = true;
                 }
+                _ => {}
+            }
+            results.push(result);
 
-                self.storage.lock().unwrap().set_int(format!("uc-stored-timestamp: {}", uc.name), new_rulesets_timestamp);
-                some_updated = true;
-            } else {
-                info!("{}: No new ruleset bundle discovered.", uc.name);
+            if cancelled {
+                break;
             }
         }
 
-        for uc in self.update_channels.get_all().iter().filter(|uc| uc.format == UpdateChannelFormat::Bloom) {
-            if let Some(new_bloom_timestamp) = self.check_for_new_updates(uc) {
-                info!("{}: A new bloom filter has been released.  Downloading now.", uc.name);
-
-                let (signature, bloom_metadata, bloom) = match self.get_new_bloom(new_bloom_timestamp, uc) {
-                    Ok(rs_tuple) => rs_tuple,
-                    Err(err) => {
-                        error!("{:?}", err);
-                        continue;
-                    }
-                };
-
-                if let Err(err) = self.verify_and_store_new_bloom(signature, bloom_metadata, bloom, new_bloom_timestamp, uc) {
-                    error!("{:?}", err);
-                    continue;
-                }
-
-                self.storage.lock().unwrap().set_int(format!("uc-stored-timestamp: {}", uc.name), new_bloom_timestamp);
-                some_updated = true;
-             }
+        if cancelled {
+            self.restore_int(String::from("last-checked"), last_checked);
         }
 
         if some_updated {
             self.apply_stored_updates();
         }
+
+        results
     }
 
-    /// Modify underlying rulesets or bloom structs to apply the stored updates
-    pub fn apply_stored_updates(&mut self) {
-        type OkRuleSetsResult = (Value, Option<String>, bool);
-        type OkBloomResult = bloomfilter::Bloom<str>;
+    /// Set or remove the integer stored under `key`, to restore it to an earlier value
+    fn restore_int(&self, key: String, value: Option<usize>) {
+        let mut storage = self.storage.lock().unwrap();
+        match value {
+            Some(value) => storage.set_int(key, value),
+            None => storage.remove(key),
+        }
+    }
 
-        // TODO: Use futures to asynchronously apply stored updates
-        let rulesets_closure = |uc: &UpdateChannel| -> Result<OkRuleSetsResult, Box<dyn Error>> {
-            match self.storage.lock().unwrap().get_string(format!("rulesets: {}", &uc.name)) {
-                Some(rulesets_json_string) => {
-                    info!("{}: Applying stored rulesets.", &uc.name);
+    /// Returns the result of an update channel which could not be updated because of the given
+    /// error.  It is reported as `Cancelled` only if the cancellation token stopped a download,
+    /// and as `Errored` otherwise, even if the token has been set since
+    fn failed(uc: &UpdateChannel, err: Box<dyn Error>) -> UpdateChannelResult {
+        if let Some(UpdaterError::Cancelled { .. }) = err.downcast_ref::<UpdaterError>() {
+            return UpdateChannelResult::new(&uc.name, UpdateChannelStatus::Cancelled);
+        }
 
-                    let rulesets_json_value: Value = serde_json::from_str(&rulesets_json_string)?;
-                    let inner_rulesets: Value = rulesets_json_value.get("rulesets").unwrap().clone();
-                    Ok((inner_rulesets, uc.scope.clone(), uc.replaces_default_rulesets))
-                }
-                None => Err(Box::new(UpdaterError::new(format!("{} Could not retrieve stored rulesets", &uc.name))))
-            }
-        };
+        error!("{:?}", err);
+        UpdateChannelResult::new(&uc.name, UpdateChannelStatus::Errored(err.to_string()))
+    }
 
-        let mut rulesets_tuple_results = vec![];
-        for uc in self.update_channels.get_all().iter().filter(|uc| uc.format == UpdateChannelFormat::RuleSets) {
-            rulesets_tuple_results.push(rulesets_closure(uc));
-        }
-        let rulesets_tuples: Vec<OkRuleSetsResult> = rulesets_tuple_results.into_iter().filter(|rt| rt.is_ok()).map(|rt| rt.unwrap()).collect();
-        let replaces = rulesets_tuples.iter().fold(false, |acc, rt| {
-            if rt.2 {
-                true
-            } else {
-                acc
+    /// Check a single ruleset update channel for updates, downloading and storing any found
+    fn check_rulesets_channel(&self, uc: &UpdateChannel, extension_timestamp: Timestamp) -> UpdateChannelResult {
+        if let Some(new_rulesets_timestamp) = self.check_for_new_updates(uc) {
+            if uc.replaces_default_rulesets && extension_timestamp > new_rulesets_timestamp {
+                channel_log!(info, uc.name, action = "skip", timestamp = new_rulesets_timestamp, "A new ruleset bundle has been released, but it is older than the extension-bundled rulesets it replaces.  Skipping.");
+                return UpdateChannelResult::new(&uc.name, UpdateChannelStatus::Skipped(String::from("The ruleset bundle is older than the extension-bundled rulesets it replaces")));
             }
-        });
+            channel_log!(info, uc.name, action = "download", timestamp = new_rulesets_timestamp, "A new ruleset bundle has been released.  Downloading now.");
 
-        let mut rs = self.rulesets.lock().unwrap();
-        rs.clear();
+            if let Err(err) = self.fetch_and_store_rulesets(new_rulesets_timestamp, uc) {
+                return Self::failed(uc, err);
+            }
 
-        for rt in rulesets_tuples {
-            rs.add_all_from_serde_value(rt.0, ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &rt.1);
+            self.storage.lock().unwrap().set_int(format!("uc-stored-timestamp: {}", uc.name), new_rulesets_timestamp);
+            UpdateChannelResult::new(&uc.name, UpdateChannelStatus::Updated(new_rulesets_timestamp))
+        } else {
+            channel_log!(info, uc.name, action = "no_update", "No new ruleset bundle discovered.");
+            UpdateChannelResult::new(&uc.name, UpdateChannelStatus::NoUpdate)
         }
+    }
 
-        if !replaces && self.default_rulesets.is_some() {
-            rs.add_all_from_json_string(&self.default_rulesets.clone().unwrap(), ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None);
-        }
+    /// Check a single bloom filter update channel for updates, downloading and storing any found
+    fn check_bloom_channel(&self, uc: &UpdateChannel) -> UpdateChannelResult {
+        if let Some(new_bloom_timestamp) = self.check_for_new_updates(uc) {
+            channel_log!(info, uc.name, action = "download", timestamp = new_bloom_timestamp, "A new bloom filter has been released.  Downloading now.");
+
+            let (signature, bloom_metadata, bloom) = match self.get_new_bloom(new_bloom_timestamp, uc) {
+                Ok(rs_tuple) => rs_tuple,
+                Err(err) => return Self::failed(uc, err),
+            };
+
+            if let Err(err) = self.verify_and_store_new_bloom(signature, bloom_metadata, bloom, new_bloom_timestamp, uc) {
+                return Self::failed(uc, err);
+            }
+
+            self.storage.lock().unwrap().set_int(format!("uc-stored-timestamp: {}", uc.name), new_bloom_timestamp);
+            UpdateChannelResult::new(&uc.name, UpdateChannelStatus::Updated(new_bloom_timestamp))
+        } else {
+            channel_log!(info, uc.name, action = "no_update", "No new bloom filter discovered.");
+            UpdateChannelResult::new(&uc.name, UpdateChannelStatus::NoUpdate)
+        }
+    }
+
+    /// Verify, store and apply a ruleset bundle which has already been downloaded by the caller,
+    /// without making any network requests
+    ///
+    /// # Arguments
+    ///
+    /// * `channel_name` - The name of the ruleset update channel the bundle was published on
+    /// * `signature` - The signature of the bundle
+    /// * `rulesets` - The gzipped rulesets bundle
+    /// * `timestamp` - The timestamp of the bundle, which must match the timestamp in the signed
+    ///   rulesets JSON
+    pub fn apply_bundle(&mut self, channel_name: &str, signature: Vec<u8>, rulesets: Vec<u8>, timestamp: Timestamp) -> Result<(), Box<dyn Error>> {
+        {
+            let uc = match self.update_channels.get_all().iter().find(|uc| uc.name == channel_name && uc.format == UpdateChannelFormat::RuleSets) {
+                Some(uc) => uc,
+                None => return Err(Box::new(UpdaterError::UnknownChannel { name: channel_name.to_string() }))
+            };
+
+            self.verify_and_store_new_rulesets(signature, rulesets, timestamp, uc)?;
+
+            let mut storage = self.storage.lock().unwrap();
+            storage.set_int(format!("uc-timestamp: {}", uc.name), timestamp);
+            storage.set_int(format!("uc-stored-timestamp: {}", uc.name), timestamp);
+        }
+
+        self.apply_stored_updates();
+        Ok(())
+    }
+
+    /// Modify underlying rulesets or bloom structs to apply the stored updates
+    pub fn apply_stored_updates(&mut self) {
+        type OkRuleSetsResult = (Value, Option<String>, bool, String, i32);
+        type OkBloomResult = bloomfilter::Bloom<str>;
+
+        // TODO: Use futures to asynchronously apply stored updates
+        let rulesets_closure = |uc: &UpdateChannel| -> Result<OkRuleSetsResult, Box<dyn Error>> {
+            match self.load_stored_rulesets(uc)? {
+                Some(rulesets_json_string) => {
+                    channel_log!(info, uc.name, action = "apply", "Applying stored rulesets.");
+
+                    let rulesets_json_value: Value = serde_json::from_str(&rulesets_json_string)?;
+                    let inner_rulesets: Value = match rulesets_json_value.get("rulesets") {
+                        Some(inner_rulesets) => inner_rulesets.clone(),
+                        None => return Err(Box::new(UpdaterError::MetadataParse { name: uc.name.clone(), field: String::from("rulesets") }))
+                    };
+                    Ok((inner_rulesets, uc.scope.clone(), uc.replaces_default_rulesets, uc.name.clone(), uc.priority))
+                }
+                None => Err(Box::new(UpdaterError::MissingStoredUpdate { name: uc.name.clone() }))
+            }
+        };
+
+        let mut rulesets_tuple_results = vec![];
+        for uc in self.update_channels.get_all().iter().filter(|uc| uc.format == UpdateChannelFormat::RuleSets) {
+            let result = rulesets_closure(uc);
+            if let Err(err) = &result {
+                if !matches!(err.downcast_ref(), Some(UpdaterError::MissingStoredUpdate { .. })) {
+                    channel_log!(warn, uc.name, action = "skip", "Skipping corrupt stored rulesets: {}", err);
+                }
+            }
+            rulesets_tuple_results.push(result);
+        }
+        let rulesets_tuples: Vec<OkRuleSetsResult> = rulesets_tuple_results.into_iter().filter(|rt| rt.is_ok()).map(|rt| rt.unwrap()).collect();
+        let replaces = rulesets_tuples.iter().fold(false, |acc, rt| {
+            if rt.2 {
+                true
+            } else {
+                acc
+            }
+        });
+
+        // Build the new rulesets before replacing the shared rulesets, so that rewriting is only
+        // held up, if at all, for the replacement itself
+        let mut rs = RuleSets::new();
+        for rt in rulesets_tuples {
+            rs.add_all_from_serde_value_with_source(rt.0, ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &rt.1, &Some(rt.3), rt.4);
+        }
+
+        if let (false, Some(default_rulesets)) = (replaces, &self.default_rulesets) {
+            if let Err(err) = rs.try_add_all_from_json_string(default_rulesets, ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None) {
+                error!("Could not parse the default rulesets: {}", err);
+            }
+        }
+        shared::store(&self.rulesets, rs);
 
 
         let bloom_closure = |uc: &UpdateChannel| -> Result<OkBloomResult, Box<dyn Error>> {
             let storage = self.storage.lock().unwrap();
             match storage.get_bytes(format!("bloom: {}", &uc.name)) {
                 Some(bloom) => {
-                    info!("{}: Applying stored bloom filter.", &uc.name);
+                    channel_log!(info, uc.name, action = "apply", "Applying stored bloom filter.");
 
                     let bitmap_bits = storage.get_int(format!("bloom_bitmap_bits: {}", &uc.name)).unwrap() as u64;
                     let k_num = storage.get_int(format!("bloom_k_num: {}", &uc.name)).unwrap() as u32;
@@ -483,7 +1205,7 @@ impl Updater {
 
                     Ok(Bloom::from_existing(&bloom, bitmap_bits, k_num, [(sip_keys_0_0, sip_keys_0_1), (sip_keys_1_0, sip_keys_1_1)]))
                 },
-                None => Err(Box::new(UpdaterError::new(format!("{} Could not retrieve stored bloom filter", &uc.name))))
+                None => Err(Box::new(UpdaterError::MissingStoredUpdate { name: uc.name.clone() }))
             }
         };
 
@@ -494,14 +1216,38 @@ impl Updater {
                 blooms.push(bloom);
             }
         }
+
+        drop(blooms);
+        if let Some(callback) = &self.on_applied {
+            callback();
+        }
     }
 
-    /// Return the time until we should check for new rulesets, in seconds
+    /// Return the time until we should check for new rulesets, in seconds.  If the last check is
+    /// recorded as being in the future (for instance, because the clock has moved backwards), the
+    /// record can't be trusted and a check is due immediately.  The interval is varied by the
+    /// configured jitter, see `set_jitter_fraction`
     pub fn time_to_next_check(&self) -> usize {
         let last_checked = self.storage.lock().unwrap().get_int(String::from("last-checked")).unwrap_or(0);
         let current_timestamp = Self::current_timestamp();
+        if last_checked > current_timestamp {
+            return 0;
+        }
         let secs_since_last_checked = current_timestamp - last_checked;
-        cmp::max(0, self.periodicity as isize - secs_since_last_checked as isize) as usize
+        self.jittered_periodicity(last_checked).saturating_sub(secs_since_last_checked)
+    }
+
+    /// Returns the periodicity varied by the jitter for the check made at `last_checked`.  The
+    /// jitter is derived from the seed and the time of the last check, so it is stable between
+    /// calls until the next check is made
+    fn jittered_periodicity(&self, last_checked: Timestamp) -> usize {
+        if self.jitter_fraction == 0.0 {
+            return self.periodicity;
+        }
+
+        let unit = splitmix64(self.jitter_seed ^ last_checked as u64) as f64 / u64::MAX as f64;
+        let offset = (unit * 2.0 - 1.0) * self.jitter_fraction * self.periodicity as f64;
+        (self.periodicity as f64 + offset).round().max(0.0) as usize
     }
 
     /// Clear the stored rulesets for any update channels which replace the default rulesets.  This
@@ -510,14 +1256,50 @@ impl Updater {
     pub fn clear_replacement_update_channels(&self) {
         for uc in self.update_channels.get_all() {
             if uc.replaces_default_rulesets {
-                self.storage.lock().unwrap().set_int(format!("uc-timestamp: {}", &uc.name), 0);
-                self.storage.lock().unwrap().set_int(format!("uc-stored-timestamp: {}", &uc.name), 0);
-                self.storage.lock().unwrap().set_string(format!("rulesets: {}", &uc.name), String::from(""));
+                let mut storage = self.storage.lock().unwrap();
+                storage.remove(format!("uc-timestamp: {}", &uc.name));
+                storage.remove(format!("uc-stored-timestamp: {}", &uc.name));
+                storage.remove(format!("rulesets: {}", &uc.name));
+                storage.remove(format!("rulesets-gz: {}", &uc.name));
+                storage.remove(format!("uc-timestamp-etag: {}", &uc.name));
+                storage.remove(format!("uc-timestamp-last-modified: {}", &uc.name));
             }
         }
     }
 }
 
+/// Returns the number of bits set within the first `bits` bits of a bloom filter bitmap, whose
+/// bits are ordered from the most significant bit of each byte
+fn count_bits_set(bitmap: &[u8], bits: u64) -> u64 {
+    let full_bytes = ((bits / 8) as usize).min(bitmap.len());
+    let mut count: u64 = bitmap[..full_bytes].iter().map(|byte| byte.count_ones() as u64).sum();
+    let remaining_bits = bits % 8;
+    if remaining_bits > 0 {
+        if let Some(byte) = bitmap.get(full_bytes) {
+            count += (byte >> (8 - remaining_bits)).count_ones() as u64;
+        }
+    }
+    count
+}
+
+/// Returns a random seed, using the randomly keyed hasher from the standard library
+fn random_seed() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// Mixes the bits of `x`, returning a well-distributed pseudorandom value
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Returns whether the given HTTP status code is a 2XX code
+fn is_success(status_code: u16) -> bool {
+    (200..300).contains(&status_code)
+}
+
 pub trait NewUpdaterWithBloom {
     fn new(rulesets: ThreadSafeRuleSets, blooms: ThreadSafeBloomVec, update_channels: UpdateChannels, storage: ThreadSafeStorage, default_rulesets: Option<String>, periodicity: usize) -> Updater;
 }
@@ -528,7 +1310,7 @@ impl NewUpdaterWithBloom for Updater {
     ///
     /// # Arguments
     ///
-    /// * `rulesets` - A ruleset struct to update, wrapped in an Arc<Mutex>
+    /// * `rulesets` - A ruleset struct to update, shared as ThreadSafeRuleSets
     /// * `blooms` - A bloom vec to update, wrapped in an Arc<Mutex>
     /// * `update_channels` - The update channels where to look for new rulesets
     /// * `storage` - The storage engine for key-value pairs, wrapped in an Arc<Mutex>
@@ -536,14 +1318,11 @@ impl NewUpdaterWithBloom for Updater {
     /// may not be replaced by updates
     /// * `periodicity` - The interval to check for new rulesets
     fn new(rulesets: ThreadSafeRuleSets, blooms: ThreadSafeBloomVec, update_channels: UpdateChannels, storage: ThreadSafeStorage, default_rulesets: Option<String>, periodicity: usize) -> Updater {
-        Updater {
-            rulesets,
-            blooms,
-            update_channels,
-            storage,
-            default_rulesets,
-            periodicity,
+        let mut builder = UpdaterBuilder::new(rulesets, update_channels, storage).blooms(blooms).periodicity(periodicity);
+        if let Some(default_rulesets) = default_rulesets {
+            builder = builder.default_rulesets(default_rulesets);
         }
+        builder.build()
     }
 }
 
@@ -561,11 +1340,11 @@ mod tests {
     #[test]
     fn updates_correctly() {
         let s: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
-        let rs = Arc::new(Mutex::new(RuleSets::new()));
+        let rs = shared::new(RuleSets::new());
         let rs2 = Arc::clone(&rs);
         let b: ThreadSafeBloomVec = Arc::new(Mutex::new(Vec::new()));
         let b2 = Arc::clone(&b);
-        assert_eq!(rs2.lock().unwrap().count_targets(), 0);
+        assert_eq!(shared::load(&rs2).count_targets(), 0);
 
         let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
         let ucs = UpdateChannels::from(&update_channels_string[..]);
@@ -573,17 +1352,800 @@ mod tests {
         let mut updater = <Updater as NewUpdaterWithBloom>::new(rs, b, ucs, s, None, 15);
         updater.perform_check();
 
-        assert!(rs2.lock().unwrap().count_targets() > 0);
+        assert!(shared::load(&rs2).count_targets() > 0);
         assert_eq!(b2.lock().unwrap()[0].check("news.example.com"), true);
     }
 
+    #[test]
+    fn reports_results_of_check() {
+        let s: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let rs = shared::new(RuleSets::new());
+        let b: ThreadSafeBloomVec = Arc::new(Mutex::new(Vec::new()));
+
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let ucs = UpdateChannels::from(&update_channels_string[..]);
+
+        // Pretend we already hold updates far newer than anything published
+        s.lock().unwrap().set_int(String::from("uc-timestamp: EFF (Full)"), usize::MAX);
+        s.lock().unwrap().set_int(String::from("uc-timestamp: Bloom Test"), usize::MAX);
+
+        let mut responses = HashMap::new();
+        responses.insert(String::from("https://www.https-rulesets.org/v1//latest-rulesets-timestamp"), (200, b"1000\n".to_vec()));
+        responses.insert(String::from("https://www.https-rulesets.org/test/bloom/latest-bloom-timestamp"), (200, b"1000\n".to_vec()));
+
+        let mut updater = <Updater as NewUpdaterWithBloom>::new(rs, b, ucs, s, None, 15);
+        updater.set_fetcher(Box::new(MockFetcher(responses)));
+        assert_eq!(updater.perform_check(), vec![
+            UpdateChannelResult::new("EFF (Full)", UpdateChannelStatus::NoUpdate),
+            UpdateChannelResult::new("Bloom Test", UpdateChannelStatus::NoUpdate),
+        ]);
+    }
+
+    fn sign_rsa_pss(key: &openssl::pkey::PKey<openssl::pkey::Private>, data: &[u8]) -> Vec<u8> {
+        let mut signer = openssl::sign::Signer::new(MessageDigest::sha256(), key).unwrap();
+        signer.set_rsa_padding(Padding::PKCS1_PSS).unwrap();
+        signer.update(data).unwrap();
+        signer.sign_to_vec().unwrap()
+    }
+
+    #[test]
+    fn distinguishes_truncated_and_corrupt_bundles() {
+        use flate2::{write::GzEncoder, Compression};
+        use openssl::{pkey::PKey, rsa::Rsa};
+        use std::io::Write;
+
+        let s: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let rs = shared::new(RuleSets::new());
+
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let mut ucs = UpdateChannels::from(&update_channels_string[..]);
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        ucs.get_all_mut()[0].keys = vec![PKey::public_key_from_pem(&key.public_key_to_pem().unwrap()).unwrap()];
+
+        let rulesets_json = format!("{{\"timestamp\": 1000, \"rulesets\": {}}}", fs::read_to_string("tests/mock_rulesets.json").unwrap());
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(rulesets_json.as_bytes()).unwrap();
+        let bundle = encoder.finish().unwrap();
+
+        let mut updater = Updater::new(rs, ucs, s, None, 15);
+
+        let truncated = bundle[..bundle.len() / 2].to_vec();
+        let err = updater.apply_bundle("EFF (Full)", sign_rsa_pss(&key, &truncated), truncated, 1000).unwrap_err();
+        assert_eq!(err.downcast_ref::<UpdaterError>(), Some(&UpdaterError::DecompressFailed { name: String::from("EFF (Full)"), timestamp: 1000, truncated: true }));
+
+        let mut corrupt = bundle.clone();
+        for byte in corrupt[10..].iter_mut() {
+            *byte = 0xff;
+        }
+        let err = updater.apply_bundle("EFF (Full)", sign_rsa_pss(&key, &corrupt), corrupt, 1000).unwrap_err();
+        assert_eq!(err.downcast_ref::<UpdaterError>(), Some(&UpdaterError::DecompressFailed { name: String::from("EFF (Full)"), timestamp: 1000, truncated: false }));
+    }
+
+    #[test]
+    fn refuses_expired_certificates() {
+        use openssl::{pkey::PKey, rsa::Rsa};
+        use update_channels::tests::issue_certificate;
+
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let mut ucs = UpdateChannels::from(&update_channels_string[..]);
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let uc = &mut ucs.get_all_mut()[0];
+        uc.keys = vec![PKey::public_key_from_pem(&key.public_key_to_pem().unwrap()).unwrap()];
+        uc.certificates = vec![issue_certificate(&key, &key, Some(30), false)];
+
+        let signature = sign_rsa_pss(&key, b"rulesets");
+        assert!(Updater::verify_signature(uc, &signature, b"rulesets").unwrap());
+
+        uc.certificates = vec![issue_certificate(&key, &key, None, false)];
+        let err = Updater::verify_signature(uc, &signature, b"rulesets").unwrap_err();
+        assert_eq!(err.downcast_ref::<UpdaterError>(), Some(&UpdaterError::CertificateExpired { name: String::from("EFF (Full)") }));
+    }
+
+    #[test]
+    fn applies_bundle() {
+        use flate2::{write::GzEncoder, Compression};
+        use openssl::{pkey::PKey, rsa::Rsa};
+        use std::io::Write;
+
+        let s: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let rs = shared::new(RuleSets::new());
+        let rs2 = Arc::clone(&rs);
+
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let mut ucs = UpdateChannels::from(&update_channels_string[..]);
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        ucs.get_all_mut()[0].keys = vec![PKey::public_key_from_pem(&key.public_key_to_pem().unwrap()).unwrap()];
+
+        let rulesets_json = format!("{{\"timestamp\": 1000, \"rulesets\": {}}}", fs::read_to_string("tests/mock_rulesets.json").unwrap());
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(rulesets_json.as_bytes()).unwrap();
+        let bundle = encoder.finish().unwrap();
+
+        let signature = sign_rsa_pss(&key, &bundle);
+
+        let mut updater = Updater::new(rs, ucs, Arc::clone(&s), None, 15);
+        updater.set_max_decompressed_bytes(rulesets_json.len() - 1);
+        assert!(updater.apply_bundle("EFF (Full)", signature.clone(), bundle.clone(), 1000).is_err());
+        updater.set_max_decompressed_bytes(rulesets_json.len());
+
+        let err = updater.apply_bundle("Nonexistent", signature.clone(), bundle.clone(), 1000).unwrap_err();
+        assert_eq!(err.downcast_ref::<UpdaterError>(), Some(&UpdaterError::UnknownChannel { name: String::from("Nonexistent") }));
+        let err = updater.apply_bundle("EFF (Full)", signature.clone(), bundle.clone(), 999).unwrap_err();
+        assert_eq!(err.downcast_ref::<UpdaterError>(), Some(&UpdaterError::TimestampMismatch { name: String::from("EFF (Full)") }));
+        let err = updater.apply_bundle("EFF (Full)", vec![0; signature.len()], bundle.clone(), 1000).unwrap_err();
+        assert_eq!(err.downcast_ref::<UpdaterError>(), Some(&UpdaterError::SignatureInvalid { name: String::from("EFF (Full)") }));
+        assert_eq!(shared::load(&rs2).count_targets(), 0);
+
+        updater.apply_bundle("EFF (Full)", signature, bundle.clone(), 1000).unwrap();
+        assert!(shared::load(&rs2).count_targets() > 0);
+        assert_eq!(s.lock().unwrap().get_int(String::from("uc-stored-timestamp: EFF (Full)")), Some(1000));
+        assert_eq!(s.lock().unwrap().get_bytes(String::from("rulesets-gz: EFF (Full)")), Some(bundle));
+        assert_eq!(s.lock().unwrap().get_string(String::from("rulesets: EFF (Full)")), None);
+    }
+
+    #[test]
+    fn gets_stored_rulesets_json() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        let s: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let rs = shared::new(RuleSets::new());
+
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let ucs = UpdateChannels::from(&update_channels_string[..]);
+        let updater = Updater::new(rs, ucs, Arc::clone(&s), None, 15);
+        assert_eq!(updater.get_stored_rulesets_json("EFF (Full)"), None);
+
+        let rulesets_json = String::from("{\"timestamp\": 1000, \"rulesets\": []}");
+        s.lock().unwrap().set_string(String::from("rulesets: EFF (Full)"), rulesets_json.clone());
+        assert_eq!(updater.get_stored_rulesets_json("EFF (Full)"), Some(rulesets_json.clone()));
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(rulesets_json.replace("1000", "2000").as_bytes()).unwrap();
+        s.lock().unwrap().set_bytes(String::from("rulesets-gz: EFF (Full)"), encoder.finish().unwrap());
+        assert_eq!(updater.get_stored_rulesets_json("EFF (Full)"), Some(rulesets_json.replace("1000", "2000")));
+
+        s.lock().unwrap().set_string(String::from("rulesets: Bloom Test"), rulesets_json);
+        assert_eq!(updater.get_stored_rulesets_json("Bloom Test"), None);
+        assert_eq!(updater.get_stored_rulesets_json("Nonexistent"), None);
+    }
+
+    #[test]
+    fn skips_corrupt_stored_rulesets() {
+        let s: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let rs = shared::new(RuleSets::new());
+
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let ucs = UpdateChannels::from(&update_channels_string[..]);
+
+        s.lock().unwrap().set_string(String::from("rulesets: EFF (Full)"), String::from("{\"rulesets\": [{"));
+
+        let default_rulesets = String::from(r#"[{"name": "Default", "target": ["example.com"], "rule": [{"from": "^http:", "to": "https:"}]}]"#);
+        let mut updater = Updater::new(Arc::clone(&rs), ucs, s, Some(default_rulesets), 15);
+        updater.apply_stored_updates();
+
+        assert_eq!(shared::load(&rs).count_targets(), 1);
+    }
+
+    #[test]
+    fn applies_stored_bloom() {
+        let s: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let rs = shared::new(RuleSets::new());
+        let b: ThreadSafeBloomVec = Arc::new(Mutex::new(Vec::new()));
+        let b2 = Arc::clone(&b);
+
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let ucs = UpdateChannels::from(&update_channels_string[..]);
+
+        s.lock().unwrap().set_batch(vec![
+            (String::from("bloom: Bloom Test"), StorageValue::Bytes(fs::read("tests/hosts.bf").unwrap())),
+            (String::from("bloom_bitmap_bits: Bloom Test"), StorageValue::Int(32)),
+            (String::from("bloom_k_num: Bloom Test"), StorageValue::Int(8)),
+            (String::from("bloom_sip_keys_0_0: Bloom Test"), StorageValue::Int(14665750518300404984)),
+            (String::from("bloom_sip_keys_0_1: Bloom Test"), StorageValue::Int(12873651473193462006)),
+            (String::from("bloom_sip_keys_1_0: Bloom Test"), StorageValue::Int(9973946878825591628)),
+            (String::from("bloom_sip_keys_1_1: Bloom Test"), StorageValue::Int(7119699906358194664)),
+        ]);
+
+        let mut updater = <Updater as NewUpdaterWithBloom>::new(rs, b, ucs, s, None, 15);
+        let applied_blooms = Arc::new(Mutex::new(None));
+        let (b3, applied_blooms2) = (Arc::clone(&b2), Arc::clone(&applied_blooms));
+        updater.on_applied(Box::new(move || {
+            *applied_blooms2.lock().unwrap() = Some(b3.lock().unwrap().len());
+        }));
+        updater.apply_stored_updates();
+
+        assert_eq!(*applied_blooms.lock().unwrap(), Some(1));
+        assert_eq!(b2.lock().unwrap().len(), 1);
+        assert!(b2.lock().unwrap()[0].check("news.example.com"));
+        assert!(!b2.lock().unwrap()[0].check("weather.example.com"));
+    }
+
+    #[test]
+    fn reports_bloom_stats() {
+        let s: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let ucs = UpdateChannels::from(&update_channels_string[..]);
+        let updater = Updater::new(shared::new(RuleSets::new()), ucs, Arc::clone(&s), None, 15);
+        assert!(updater.bloom_stats().is_empty());
+
+        s.lock().unwrap().set_batch(vec![
+            (String::from("bloom: Bloom Test"), StorageValue::Bytes(fs::read("tests/hosts.bf").unwrap())),
+            (String::from("bloom_bitmap_bits: Bloom Test"), StorageValue::Int(32)),
+            (String::from("bloom_k_num: Bloom Test"), StorageValue::Int(8)),
+        ]);
+
+        let stats = updater.bloom_stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].name, "Bloom Test");
+        assert_eq!((stats[0].bitmap_bits, stats[0].k_num, stats[0].bits_set), (32, 8, 19));
+        assert!((stats[0].false_positive_rate - (19.0f64 / 32.0).powi(8)).abs() < 1e-12);
+
+        assert_eq!(count_bits_set(&[0xff, 0xf0], 12), 12);
+        assert_eq!(count_bits_set(&[0xff, 0x0f], 12), 8);
+    }
+
+    #[test]
+    fn verifies_signatures() {
+        use openssl::{pkey::PKey, rsa::Rsa, sign::Signer};
+
+        let data = b"{\"timestamp\": 1}";
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let mut ucs = UpdateChannels::from(&update_channels_string[..]);
+        let uc = &mut ucs.get_all_mut()[0];
+
+        let rsa_key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut signer = Signer::new(MessageDigest::sha256(), &rsa_key).unwrap();
+        signer.set_rsa_padding(Padding::PKCS1_PSS).unwrap();
+        signer.update(data).unwrap();
+        let rsa_signature = signer.sign_to_vec().unwrap();
+        uc.keys.push(PKey::public_key_from_pem(&rsa_key.public_key_to_pem().unwrap()).unwrap());
+        assert!(Updater::verify_signature(uc, &rsa_signature, data).unwrap());
+        assert!(!Updater::verify_signature(uc, &rsa_signature, b"tampered").unwrap());
+
+        let ed25519_key = PKey::generate_ed25519().unwrap();
+        let ed25519_signature = Signer::new_without_digest(&ed25519_key).unwrap().sign_oneshot_to_vec(data).unwrap();
+        uc.signature_algorithm = SignatureAlgorithm::Ed25519;
+        uc.keys = vec![PKey::public_key_from_pem(&ed25519_key.public_key_to_pem().unwrap()).unwrap()];
+        assert!(Updater::verify_signature(uc, &ed25519_signature, data).unwrap());
+        assert!(!Updater::verify_signature(uc, &ed25519_signature, b"tampered").unwrap());
+    }
+
+    #[test]
+    fn verifies_signatures_with_keys_of_different_sizes() {
+        use openssl::{pkey::PKey, rsa::Rsa};
+
+        let data = b"{\"timestamp\": 1}";
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let mut ucs = UpdateChannels::from(&update_channels_string[..]);
+        let uc = &mut ucs.get_all_mut()[0];
+
+        let old_key = PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap();
+        let new_key = PKey::from_rsa(Rsa::generate(4096).unwrap()).unwrap();
+        uc.keys = vec![
+            PKey::public_key_from_pem(&old_key.public_key_to_pem().unwrap()).unwrap(),
+            PKey::public_key_from_pem(&new_key.public_key_to_pem().unwrap()).unwrap(),
+        ];
+        assert!(Updater::verify_signature(uc, &sign_rsa_pss(&new_key, data), data).unwrap());
+        assert!(Updater::verify_signature(uc, &sign_rsa_pss(&old_key, data), data).unwrap());
+        assert!(!Updater::verify_signature(uc, &sign_rsa_pss(&new_key, data), b"tampered").unwrap());
+
+        uc.keys.reverse();
+        assert!(Updater::verify_signature(uc, &sign_rsa_pss(&old_key, data), data).unwrap());
+
+        // A key the verifier fails on altogether does not stop the remaining keys from being tried
+        let ed25519_key = PKey::generate_ed25519().unwrap();
+        uc.keys.insert(0, PKey::public_key_from_pem(&ed25519_key.public_key_to_pem().unwrap()).unwrap());
+        assert!(Updater::verify_signature(uc, &sign_rsa_pss(&new_key, data), data).unwrap());
+    }
+
+    struct MockFetcher(HashMap<String, (u16, Vec<u8>)>);
+
+    impl Fetcher for MockFetcher {
+        fn get(&self, url: &str, _max_bytes: usize) -> Result<(u16, Vec<u8>), Box<dyn Error>> {
+            Ok(self.0.get(url).cloned().unwrap_or((404, vec![])))
+        }
+    }
+
+    #[test]
+    fn fetches_with_custom_fetcher() {
+        let s: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let rs = shared::new(RuleSets::new());
+
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let ucs = UpdateChannels::from(&update_channels_string[..]);
+
+        let mut responses = HashMap::new();
+        responses.insert(String::from("https://www.https-rulesets.org/v1//latest-rulesets-timestamp"), (200, b"1000\n".to_vec()));
+        responses.insert(String::from("https://www.https-rulesets.org/v1//rulesets-signature.1000.sha256"), (200, vec![0; 512]));
+        responses.insert(String::from("https://www.https-rulesets.org/v1//default.rulesets.1000.gz"), (200, vec![0; 1024]));
+
+        let mut updater = Updater::new(rs, ucs, s, None, 15);
+        updater.set_fetcher(Box::new(MockFetcher(responses)));
+        updater.set_max_download_bytes(600);
+
+        let results = updater.perform_check();
+        assert_eq!(results[0].name, "EFF (Full)");
+        match &results[0].status {
+            UpdateChannelStatus::Errored(err) => assert_eq!(err, &UpdaterError::DownloadTooLarge { name: String::from("EFF (Full)"), file: String::from("ruleset"), max_bytes: 600 }.to_string()),
+            other => panic!("Unexpected status: {:?}", other)
+        }
+    }
+
+    #[test]
+    fn reads_local_update_channel() {
+        use flate2::{write::GzEncoder, Compression};
+        use openssl::{pkey::PKey, rsa::Rsa};
+        use std::io::Write;
+
+        let s: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let rs = shared::new(RuleSets::new());
+
+        let rulesets_json = format!("{{\"timestamp\": 1000, \"rulesets\": {}}}", fs::read_to_string("tests/mock_rulesets.json").unwrap());
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(rulesets_json.as_bytes()).unwrap();
+        let bundle = encoder.finish().unwrap();
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("https-everywhere local channel {}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("latest-rulesets-timestamp"), b"1000\n").unwrap();
+        fs::write(dir.join("rulesets-signature.1000.sha256"), sign_rsa_pss(&key, &bundle)).unwrap();
+        fs::write(dir.join("default.rulesets.1000.gz"), &bundle).unwrap();
+
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let mut ucs = UpdateChannels::from(&update_channels_string[..]);
+        let uc = &mut ucs.get_all_mut()[0];
+        uc.keys = vec![PKey::public_key_from_pem(&key.public_key_to_pem().unwrap()).unwrap()];
+        uc.update_path_prefix = format!("file://localhost{}", dir.display()).replace(' ', "%20");
+
+        let mut updater = Updater::new(Arc::clone(&rs), ucs, s, None, 15);
+        let results = updater.perform_check();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(results[0].status, UpdateChannelStatus::Updated(1000));
+        assert!(shared::load(&rs).count_targets() > 0);
+    }
+
+    /// Cancels the check once the given URL has been fetched
+    struct CancellingFetcher(MockFetcher, String, Arc<AtomicBool>);
+
+    impl Fetcher for CancellingFetcher {
+        fn get(&self, url: &str, max_bytes: usize) -> Result<(u16, Vec<u8>), Box<dyn Error>> {
+            if url == self.1 {
+                self.2.store(true, Ordering::SeqCst);
+            }
+            self.0.get(url, max_bytes)
+        }
+    }
+
+    /// Records the URLs it is asked to fetch
+    struct RecordingFetcher(MockFetcher, Arc<Mutex<Vec<String>>>);
+
+    impl Fetcher for RecordingFetcher {
+        fn get(&self, url: &str, max_bytes: usize) -> Result<(u16, Vec<u8>), Box<dyn Error>> {
+            self.1.lock().unwrap().push(url.to_string());
+            self.0.get(url, max_bytes)
+        }
+    }
+
+    /// Records the user agent of each request
+    struct UserAgentFetcher(Arc<Mutex<Vec<String>>>);
+
+    impl Fetcher for UserAgentFetcher {
+        fn get(&self, _url: &str, _max_bytes: usize) -> Result<(u16, Vec<u8>), Box<dyn Error>> {
+            Ok((404, vec![]))
+        }
+
+        fn get_with_user_agent(&self, url: &str, max_bytes: usize, user_agent: &str) -> Result<(u16, Vec<u8>), Box<dyn Error>> {
+            self.0.lock().unwrap().push(user_agent.to_string());
+            self.get(url, max_bytes)
+        }
+    }
+
+    #[test]
+    fn passes_user_agent_to_fetcher() {
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let ucs = UpdateChannels::from(&update_channels_string[..]);
+        let mut updater = Updater::new(shared::new(RuleSets::new()), ucs, Arc::new(Mutex::new(WorkingTempStorage::new())), None, 15);
+
+        let user_agents = Arc::new(Mutex::new(vec![]));
+        updater.set_fetcher(Box::new(UserAgentFetcher(Arc::clone(&user_agents))));
+        updater.get("https://www.https-rulesets.org/v1/latest-rulesets-timestamp").unwrap();
+
+        updater.set_user_agent(Some(String::from("HTTPS Everywhere/2021.7.13 (Firefox)")));
+        updater.get("https://www.https-rulesets.org/v1/latest-rulesets-timestamp").unwrap();
+
+        updater.set_user_agent(None);
+        updater.get("https://www.https-rulesets.org/v1/latest-rulesets-timestamp").unwrap();
+
+        assert_eq!(*user_agents.lock().unwrap(), vec![DEFAULT_USER_AGENT, "HTTPS Everywhere/2021.7.13 (Firefox)", DEFAULT_USER_AGENT]);
+    }
+
+    #[test]
+    fn cancels_check() {
+        let s: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let rs = shared::new(RuleSets::new());
+
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let ucs = UpdateChannels::from(&update_channels_string[..]);
+        s.lock().unwrap().set_int(String::from("last-checked"), 10);
+
+        let timestamp_url = String::from("https://www.https-rulesets.org/v1//latest-rulesets-timestamp");
+        let mut responses = HashMap::new();
+        responses.insert(timestamp_url.clone(), (200, b"1000\n".to_vec()));
+
+        let token = Arc::new(AtomicBool::new(false));
+        let mut updater = Updater::new(rs, ucs, Arc::clone(&s), None, 15);
+        updater.set_fetcher(Box::new(CancellingFetcher(MockFetcher(responses), timestamp_url, Arc::clone(&token))));
+        updater.set_cancellation_token(Arc::clone(&token));
+
+        let results = updater.perform_check();
+        assert_eq!(results, vec![UpdateChannelResult::new("EFF (Full)", UpdateChannelStatus::Cancelled)]);
+        assert_eq!(s.lock().unwrap().get_int(String::from("uc-timestamp: EFF (Full)")), None);
+        assert_eq!(s.lock().unwrap().get_int(String::from("last-checked")), Some(10));
+
+        assert!(updater.perform_check().is_empty());
+    }
+
+    #[test]
+    fn reports_errors_despite_cancellation() {
+        let s: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let rs = shared::new(RuleSets::new());
+
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let ucs = UpdateChannels::from(&update_channels_string[..]);
+
+        let bundle_url = String::from("https://www.https-rulesets.org/v1//default.rulesets.1000.gz");
+        let mut responses = HashMap::new();
+        responses.insert(String::from("https://www.https-rulesets.org/v1//latest-rulesets-timestamp"), (200, b"1000\n".to_vec()));
+        responses.insert(String::from("https://www.https-rulesets.org/v1//rulesets-signature.1000.sha256"), (200, vec![0; 512]));
+        responses.insert(bundle_url.clone(), (200, vec![0; 1024]));
+
+        let token = Arc::new(AtomicBool::new(false));
+        let mut updater = Updater::new(rs, ucs, s, None, 15);
+        updater.set_fetcher(Box::new(CancellingFetcher(MockFetcher(responses), bundle_url, Arc::clone(&token))));
+        updater.set_cancellation_token(Arc::clone(&token));
+
+        let results = updater.perform_check();
+        assert_eq!(results.len(), 1);
+        assert!(matches!(results[0].status, UpdateChannelStatus::Errored(_)), "Unexpected status: {:?}", results[0].status);
+    }
+
+    #[test]
+    fn applies_rulesets_diff() {
+        use openssl::{pkey::PKey, rsa::Rsa};
+
+        let s: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let rs = shared::new(RuleSets::new());
+
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let mut ucs = UpdateChannels::from(&update_channels_string[..]);
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        ucs.get_all_mut()[0].keys = vec![PKey::public_key_from_pem(&key.public_key_to_pem().unwrap()).unwrap()];
+
+        {
+            let mut storage = s.lock().unwrap();
+            storage.set_string(String::from("rulesets: EFF (Full)"), String::from(r#"{"timestamp": 1000, "rulesets": [
+                {"name": "A", "target": ["a.example.com"], "rule": [{"from": "^http:", "to": "https:"}]},
+                {"name": "B", "target": ["b.example.com"], "rule": [{"from": "^http:", "to": "https:"}]}
+            ]}"#));
+            storage.set_int(String::from("uc-timestamp: EFF (Full)"), 1000);
+            storage.set_int(String::from("uc-stored-timestamp: EFF (Full)"), 1000);
+        }
+
+        let diff = br#"{"from": 1000, "to": 2000, "remove": ["A"], "add": [
+            {"name": "B", "target": ["b.example.com", "www.b.example.com"], "rule": [{"from": "^http:", "to": "https:"}]},
+            {"name": "C", "target": ["c.example.com"], "rule": [{"from": "^http:", "to": "https:"}]}
+        ]}"#.to_vec();
+
+        let mut responses = HashMap::new();
+        responses.insert(String::from("https://www.https-rulesets.org/v1//latest-rulesets-timestamp"), (200, b"2000\n".to_vec()));
+        responses.insert(String::from("https://www.https-rulesets.org/v1//rulesets-diff-signature.1000-2000.sha256"), (200, sign_rsa_pss(&key, &diff)));
+        responses.insert(String::from("https://www.https-rulesets.org/v1//rulesets-diff.1000-2000.json"), (200, diff));
+
+        let mut updater = Updater::new(Arc::clone(&rs), ucs, Arc::clone(&s), None, 15);
+        updater.set_fetcher(Box::new(MockFetcher(responses)));
+
+        let results = updater.perform_check();
+        assert_eq!(results[0].status, UpdateChannelStatus::Updated(2000));
+        assert_eq!(s.lock().unwrap().get_int(String::from("uc-stored-timestamp: EFF (Full)")), Some(2000));
+
+        assert_eq!(s.lock().unwrap().get_string(String::from("rulesets: EFF (Full)")), None);
+        let uc = &updater.update_channels.get_all()[0];
+        let stored: Value = serde_json::from_str(&updater.load_stored_rulesets(uc).unwrap().unwrap()).unwrap();
+        assert_eq!(stored["timestamp"], 2000);
+        let names: Vec<&str> = stored["rulesets"].as_array().unwrap().iter().map(|rs| rs["name"].as_str().unwrap()).collect();
+        assert_eq!(names, vec!["B", "C"]);
+        assert_eq!(shared::load(&rs).count_targets(), 3);
+        assert_eq!(shared::load(&rs).rulesets_for_target("c.example.com").unwrap()[0].source_channel(), Some("EFF (Full)"));
+    }
+
+    #[test]
+    fn falls_back_to_full_bundle_without_diff() {
+        let s: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let rs = shared::new(RuleSets::new());
+
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let ucs = UpdateChannels::from(&update_channels_string[..]);
+
+        {
+            let mut storage = s.lock().unwrap();
+            storage.set_string(String::from("rulesets: EFF (Full)"), String::from(r#"{"timestamp": 1000, "rulesets": []}"#));
+            storage.set_int(String::from("uc-stored-timestamp: EFF (Full)"), 1000);
+        }
+
+        let mut responses = HashMap::new();
+        responses.insert(String::from("https://www.https-rulesets.org/v1//latest-rulesets-timestamp"), (200, b"2000\n".to_vec()));
+        responses.insert(String::from("https://www.https-rulesets.org/v1//rulesets-signature.2000.sha256"), (200, vec![0; 512]));
+
+        let fetched = Arc::new(Mutex::new(vec![]));
+        let mut updater = Updater::new(rs, ucs, s, None, 15);
+        updater.set_fetcher(Box::new(RecordingFetcher(MockFetcher(responses), Arc::clone(&fetched))));
+
+        let results = updater.perform_check();
+        match &results[0].status {
+            UpdateChannelStatus::Errored(err) => assert_eq!(err, &UpdaterError::BadStatus { name: String::from("EFF (Full)"), file: String::from("ruleset"), code: 404 }.to_string()),
+            other => panic!("Unexpected status: {:?}", other)
+        }
+        assert_eq!(fetched.lock().unwrap()[..4], [
+            "https://www.https-rulesets.org/v1//latest-rulesets-timestamp",
+            "https://www.https-rulesets.org/v1//rulesets-diff-signature.1000-2000.sha256",
+            "https://www.https-rulesets.org/v1//rulesets-signature.2000.sha256",
+            "https://www.https-rulesets.org/v1//default.rulesets.2000.gz",
+        ]);
+    }
+
+    #[test]
+    fn skips_diff_for_old_rulesets() {
+        let s: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let rs = shared::new(RuleSets::new());
+
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let ucs = UpdateChannels::from(&update_channels_string[..]);
+
+        {
+            let mut storage = s.lock().unwrap();
+            storage.set_string(String::from("rulesets: EFF (Full)"), String::from(r#"{"timestamp": 1000, "rulesets": []}"#));
+            storage.set_int(String::from("uc-stored-timestamp: EFF (Full)"), 1000);
+        }
+
+        let mut responses = HashMap::new();
+        responses.insert(String::from("https://www.https-rulesets.org/v1//latest-rulesets-timestamp"), (200, b"2001\n".to_vec()));
+
+        let fetched = Arc::new(Mutex::new(vec![]));
+        let mut updater = Updater::new(rs, ucs, s, None, 15);
+        updater.set_fetcher(Box::new(RecordingFetcher(MockFetcher(responses), Arc::clone(&fetched))));
+        updater.set_max_diff_age(1000);
+
+        updater.perform_check();
+        assert_eq!(fetched.lock().unwrap()[..2], [
+            "https://www.https-rulesets.org/v1//latest-rulesets-timestamp",
+            "https://www.https-rulesets.org/v1//rulesets-signature.2001.sha256",
+        ]);
+    }
+
+    struct ConditionalFetcher {
+        timestamp: Vec<u8>,
+        etag: String,
+        requests: Mutex<Vec<CacheValidators>>,
+    }
+
+    impl Fetcher for Arc<ConditionalFetcher> {
+        fn get(&self, _url: &str, _max_bytes: usize) -> Result<(u16, Vec<u8>), Box<dyn Error>> {
+            Ok((200, self.timestamp.clone()))
+        }
+
+        fn get_conditional(&self, _url: &str, _max_bytes: usize, validators: &CacheValidators) -> Result<(u16, Vec<u8>, CacheValidators), Box<dyn Error>> {
+            self.requests.lock().unwrap().push(validators.clone());
+            let response_validators = CacheValidators { etag: Some(self.etag.clone()), last_modified: None };
+            if validators.etag.as_ref() == Some(&self.etag) {
+                Ok((304, vec![], response_validators))
+            } else {
+                Ok((200, self.timestamp.clone(), response_validators))
+            }
+        }
+    }
+
+    #[test]
+    fn makes_conditional_timestamp_requests() {
+        let s: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let rs = shared::new(RuleSets::new());
+
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let ucs = UpdateChannels::from(&update_channels_string[..]);
+        s.lock().unwrap().set_int(format!("uc-timestamp: {}", ucs.get_all()[0].name), 1000);
+
+        let mut updater = Updater::new(rs, ucs, Arc::clone(&s), None, 15);
+        let fetcher = Arc::new(ConditionalFetcher {
+            timestamp: b"1000\n".to_vec(),
+            etag: String::from("\"abc\""),
+            requests: Mutex::new(Vec::new()),
+        });
+        updater.set_fetcher(Box::new(Arc::clone(&fetcher)));
+
+        let uc = &updater.update_channels.get_all()[0];
+        assert_eq!(updater.check_for_new_updates(uc), None);
+        assert_eq!(updater.check_for_new_updates(uc), None);
+
+        let requests = fetcher.requests.lock().unwrap();
+        assert_eq!(requests[0], CacheValidators::default());
+        assert_eq!(requests[1].etag, Some(String::from("\"abc\"")));
+    }
+
+    #[test]
+    fn clears_replacement_update_channels() {
+        let s: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let rs = shared::new(RuleSets::new());
+
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let ucs = UpdateChannels::from(&update_channels_string[..]);
+
+        {
+            let mut storage = s.lock().unwrap();
+            storage.set_int(String::from("uc-timestamp: EFF (Full)"), 10);
+            storage.set_int(String::from("uc-stored-timestamp: EFF (Full)"), 10);
+            storage.set_string(String::from("rulesets: EFF (Full)"), String::from("{}"));
+            storage.set_bytes(String::from("rulesets-gz: EFF (Full)"), vec![]);
+        }
+
+        let updater = Updater::new(rs, ucs, Arc::clone(&s), None, 15);
+        updater.clear_replacement_update_channels();
+
+        let storage = s.lock().unwrap();
+        assert_eq!(storage.get_int(String::from("uc-timestamp: EFF (Full)")), None);
+        assert_eq!(storage.get_int(String::from("uc-stored-timestamp: EFF (Full)")), None);
+        assert_eq!(storage.get_string(String::from("rulesets: EFF (Full)")), None);
+        assert_eq!(storage.get_bytes(String::from("rulesets-gz: EFF (Full)")), None);
+    }
+
+    #[test]
+    fn verifies_stored_integrity() {
+        let s: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let rs = shared::new(RuleSets::new());
+
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let ucs = UpdateChannels::from(&update_channels_string[..]);
+        let updater = Updater::new(rs, ucs, Arc::clone(&s), None, 15);
+        let status = || updater.verify_stored_integrity()[0].1.clone();
+
+        assert_eq!(updater.verify_stored_integrity(), vec![(String::from("EFF (Full)"), IntegrityStatus::NotStored)]);
+
+        s.lock().unwrap().set_string(String::from("rulesets: EFF (Full)"), String::from(r#"{"timestamp": 1000, "rulesets": []}"#));
+        assert_eq!(status(), IntegrityStatus::Incomplete);
+
+        s.lock().unwrap().set_int(String::from("uc-stored-timestamp: EFF (Full)"), 1000);
+        assert_eq!(status(), IntegrityStatus::Valid);
+
+        s.lock().unwrap().set_int(String::from("uc-stored-timestamp: EFF (Full)"), 2000);
+        assert_eq!(status(), IntegrityStatus::TimestampMismatch);
+
+        s.lock().unwrap().set_bytes(String::from("rulesets-gz: EFF (Full)"), vec![0; 16]);
+        assert!(matches!(status(), IntegrityStatus::Corrupt(_)));
+    }
+
+    #[test]
+    fn detects_conflicts() {
+        let ruleset = |name: &str, target: &str| serde_json::json!([{"name": name, "target": [target], "rule": [{"from": "^http:", "to": "https:"}]}]);
+        let mut rs = RuleSets::new();
+        rulesets_tests::add_mock_rulesets(&mut rs);
+        rs.add_all_from_serde_value_with_source(ruleset("A", "a.example.com"), ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None, &Some(String::from("Second")), 0);
+        rs.add_all_from_serde_value_with_source(ruleset("B", "b.example.com"), ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None, &Some(String::from("First")), 0);
+        rs.add_all_from_serde_value_with_source(ruleset("A", "a.example.com"), ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None, &Some(String::from("First")), 0);
+        rs.add_all_from_serde_value_with_source(ruleset("Freerangekitten", "freerangekitten.com"), ENABLE_MIXED_RULESETS, &RULE_ACTIVE_STATES, &None, &Some(String::from("First")), 0);
+
+        let ucs = UpdateChannels::from(&fs::read_to_string("tests/update_channels.json").unwrap()[..]);
+        let updater = Updater::new(shared::new(rs), ucs, Arc::new(Mutex::new(WorkingTempStorage::new())), None, 15);
+
+        assert_eq!(updater.detect_conflicts(), vec![Conflict {
+            target: String::from("a.example.com"),
+            channels: vec![String::from("First"), String::from("Second")],
+        }]);
+    }
+
+    #[test]
+    fn reports_channel_status() {
+        let s: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let rs = shared::new(RuleSets::new());
+
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let ucs = UpdateChannels::from(&update_channels_string[..]);
+
+        {
+            let mut storage = s.lock().unwrap();
+            storage.set_int(String::from("last-checked"), 30);
+            storage.set_int(String::from("uc-timestamp: EFF (Full)"), 20);
+            storage.set_int(String::from("uc-stored-timestamp: EFF (Full)"), 10);
+        }
+
+        let updater = Updater::new(rs, ucs, Arc::clone(&s), None, 15);
+        let status = updater.get_channel_status();
+        assert_eq!(status.last_checked, Some(30));
+        assert_eq!(status.channels.len(), updater.update_channels.get_all().len());
+        assert_eq!(status.channels["EFF (Full)"], ChannelStatus {
+            last_checked: Some(30),
+            last_seen_timestamp: Some(20),
+            last_applied_timestamp: Some(10),
+        });
+    }
+
+    #[test]
+    fn time_to_next_check_handles_future_last_checked() {
+        let s: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let rs = shared::new(RuleSets::new());
+
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let ucs = UpdateChannels::from(&update_channels_string[..]);
+
+        let updater = Updater::new(rs, ucs, Arc::clone(&s), None, 15);
+        assert_eq!(updater.time_to_next_check(), 0);
+
+        s.lock().unwrap().set_int(String::from("last-checked"), Updater::current_timestamp());
+        assert!(updater.time_to_next_check() > 0);
+
+        s.lock().unwrap().set_int(String::from("last-checked"), Updater::current_timestamp() + 1000);
+        assert_eq!(updater.time_to_next_check(), 0);
+    }
+
+    #[test]
+    fn time_to_next_check_reflects_changed_periodicity() {
+        let s: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let rs = shared::new(RuleSets::new());
+
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let ucs = UpdateChannels::from(&update_channels_string[..]);
+
+        let mut updater = Updater::new(rs, ucs, Arc::clone(&s), None, 15);
+        s.lock().unwrap().set_int(String::from("last-checked"), Updater::current_timestamp());
+        assert!(updater.time_to_next_check() <= 15);
+
+        updater.set_periodicity(86400);
+        assert_eq!(updater.get_periodicity(), 86400);
+        assert!(updater.time_to_next_check() > 15);
+
+        updater.set_periodicity(0);
+        assert_eq!(updater.time_to_next_check(), 0);
+    }
+
+    #[test]
+    fn jitters_time_to_next_check() {
+        let s: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let rs = shared::new(RuleSets::new());
+
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+        let ucs = UpdateChannels::from(&update_channels_string[..]);
+
+        let mut updater = Updater::new(rs, ucs, Arc::clone(&s), None, 1000);
+        updater.set_jitter_fraction(0.1);
+        updater.set_jitter_seed(42);
+
+        let intervals: Vec<usize> = (0..20).map(|last_checked| updater.jittered_periodicity(last_checked)).collect();
+        assert!(intervals.iter().all(|interval| (900..=1100).contains(interval)));
+        assert!(intervals.iter().any(|interval| *interval != 1000));
+        assert_eq!(intervals, (0..20).map(|last_checked| updater.jittered_periodicity(last_checked)).collect::<Vec<_>>());
+
+        updater.set_jitter_seed(43);
+        assert_ne!(intervals, (0..20).map(|last_checked| updater.jittered_periodicity(last_checked)).collect::<Vec<_>>());
+
+        updater.set_jitter_fraction(0.0);
+        assert_eq!(updater.jittered_periodicity(7), 1000);
+    }
+
+    #[test]
+    fn timestamp_before_epoch_falls_back_to_zero() {
+        use std::time::Duration;
+
+        assert_eq!(Updater::timestamp_of(UNIX_EPOCH + Duration::from_secs(100)), 100);
+        assert_eq!(Updater::timestamp_of(UNIX_EPOCH - Duration::from_secs(100)), 0);
+    }
+
     #[test]
     fn is_threadsafe() {
         let s: ThreadSafeStorage = Arc::new(Mutex::new(TestStorage));
 
         let mut rs = RuleSets::new();
         rulesets_tests::add_mock_rulesets(&mut rs);
-        let rs = Arc::new(Mutex::new(rs));
+        let rs = shared::new(rs);
 
         let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
         let ucs = UpdateChannels::from(&update_channels_string[..]);
@@ -0,0 +1,189 @@
+use crate::{rulesets::ThreadSafeRuleSets, storage::ThreadSafeStorage};
+use std::sync::{Arc, Mutex};
+
+use super::{random_seed, Fetcher, HttpReqFetcher, ThreadSafeBloomVec, UpdateChannels, Updater, UpdatesAppliedCallback};
+use super::{DEFAULT_MAX_DECOMPRESSED_BYTES, DEFAULT_MAX_DIFF_AGE, DEFAULT_MAX_DOWNLOAD_BYTES};
+#[cfg(test)]
+use super::DEFAULT_USER_AGENT;
+
+/// The default interval, in seconds, to check for new rulesets
+pub const DEFAULT_PERIODICITY: usize = 86400;
+
+/// An UpdaterBuilder constructs an Updater, filling in defaults for any options which are not
+/// set.  The rulesets, update channels and storage are always required
+pub struct UpdaterBuilder {
+    rulesets: ThreadSafeRuleSets,
+    blooms: ThreadSafeBloomVec,
+    update_channels: UpdateChannels,
+    storage: ThreadSafeStorage,
+    default_rulesets: Option<String>,
+    periodicity: usize,
+    max_download_bytes: usize,
+    max_decompressed_bytes: usize,
+    max_diff_age: usize,
+    fetcher: Box<dyn Fetcher>,
+    on_applied: Option<UpdatesAppliedCallback>,
+    jitter_fraction: f64,
+    jitter_seed: Option<u64>,
+    user_agent: Option<String>,
+}
+
+impl UpdaterBuilder {
+    /// Returns a builder for an updater with the rulesets, update channels and storage specified
+    ///
+    /// # Arguments
+    ///
+    /// * `rulesets` - A ruleset struct to update, shared as ThreadSafeRuleSets
+    /// * `update_channels` - The update channels where to look for new rulesets
+    /// * `storage` - The storage engine for key-value pairs, wrapped in an Arc<Mutex>
+    pub fn new(rulesets: ThreadSafeRuleSets, update_channels: UpdateChannels, storage: ThreadSafeStorage) -> UpdaterBuilder {
+        UpdaterBuilder {
+            rulesets,
+            blooms: Arc::new(Mutex::new(vec![])),
+            update_channels,
+            storage,
+            default_rulesets: None,
+            periodicity: DEFAULT_PERIODICITY,
+            max_download_bytes: DEFAULT_MAX_DOWNLOAD_BYTES,
+            max_decompressed_bytes: DEFAULT_MAX_DECOMPRESSED_BYTES,
+            max_diff_age: DEFAULT_MAX_DIFF_AGE,
+            fetcher: Box::new(HttpReqFetcher),
+            on_applied: None,
+            jitter_fraction: 0.0,
+            jitter_seed: None,
+            user_agent: None,
+        }
+    }
+
+    /// Set the bloom vec to update.  Defaults to an empty vec
+    pub fn blooms(mut self, blooms: ThreadSafeBloomVec) -> UpdaterBuilder {
+        self.blooms = blooms;
+        self
+    }
+
+    /// Set the default rulesets, which may or may not be replaced by updates
+    pub fn default_rulesets(mut self, default_rulesets: String) -> UpdaterBuilder {
+        self.default_rulesets = Some(default_rulesets);
+        self
+    }
+
+    /// Set the interval, in seconds, to check for new rulesets.  Defaults to
+    /// `DEFAULT_PERIODICITY`
+    pub fn periodicity(mut self, periodicity: usize) -> UpdaterBuilder {
+        self.periodicity = periodicity;
+        self
+    }
+
+    /// Set the maximum size, in bytes, of any single file downloaded from an update channel.  See
+    /// `Updater::set_max_download_bytes`
+    pub fn max_download_bytes(mut self, max_download_bytes: usize) -> UpdaterBuilder {
+        self.max_download_bytes = max_download_bytes;
+        self
+    }
+
+    /// Set the maximum size, in bytes, which a downloaded ruleset bundle may decompress to.  See
+    /// `Updater::set_max_decompressed_bytes`
+    pub fn max_decompressed_bytes(mut self, max_decompressed_bytes: usize) -> UpdaterBuilder {
+        self.max_decompressed_bytes = max_decompressed_bytes;
+        self
+    }
+
+    /// Set the maximum age, in seconds, of stored rulesets which are updated with a ruleset diff.
+    /// See `Updater::set_max_diff_age`
+    pub fn max_diff_age(mut self, max_diff_age: usize) -> UpdaterBuilder {
+        self.max_diff_age = max_diff_age;
+        self
+    }
+
+    /// Set the fetcher used to make network requests.  Defaults to `HttpReqFetcher`
+    pub fn fetcher(mut self, fetcher: Box<dyn Fetcher>) -> UpdaterBuilder {
+        self.fetcher = fetcher;
+        self
+    }
+
+    /// Set a callback which is invoked each time stored updates have been applied.  See
+    /// `Updater::on_applied`
+    pub fn on_applied(mut self, callback: UpdatesAppliedCallback) -> UpdaterBuilder {
+        self.on_applied = Some(callback);
+        self
+    }
+
+    /// Set the fraction of the periodicity by which the interval between checks is randomly
+    /// varied.  See `Updater::set_jitter_fraction`
+    pub fn jitter_fraction(mut self, jitter_fraction: f64) -> UpdaterBuilder {
+        self.jitter_fraction = jitter_fraction.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Set the seed from which the jitter of each interval is derived.  Defaults to a random seed
+    pub fn jitter_seed(mut self, jitter_seed: u64) -> UpdaterBuilder {
+        self.jitter_seed = Some(jitter_seed);
+        self
+    }
+
+    /// Set the User-Agent header sent with all update requests.  Defaults to
+    /// `DEFAULT_USER_AGENT`.  See `Updater::set_user_agent`
+    pub fn user_agent(mut self, user_agent: String) -> UpdaterBuilder {
+        self.user_agent = Some(user_agent);
+        self
+    }
+
+    /// Returns the updater
+    pub fn build(self) -> Updater {
+        Updater {
+            rulesets: self.rulesets,
+            blooms: self.blooms,
+            update_channels: self.update_channels,
+            storage: self.storage,
+            default_rulesets: self.default_rulesets,
+            periodicity: self.periodicity,
+            max_download_bytes: self.max_download_bytes,
+            max_decompressed_bytes: self.max_decompressed_bytes,
+            max_diff_age: self.max_diff_age,
+            fetcher: self.fetcher,
+            on_applied: self.on_applied,
+            jitter_fraction: self.jitter_fraction,
+            jitter_seed: self.jitter_seed.unwrap_or_else(random_seed),
+            cancellation_token: None,
+            user_agent: self.user_agent,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::rulesets::shared;
+    use crate::RuleSets;
+    use crate::storage::tests::working_storage::WorkingTempStorage;
+
+    #[test]
+    fn builds_with_defaults_and_options() {
+        let s: ThreadSafeStorage = Arc::new(Mutex::new(WorkingTempStorage::new()));
+        let rs = shared::new(RuleSets::new());
+
+        let update_channels_string = fs::read_to_string("tests/update_channels.json").unwrap();
+
+        let updater = UpdaterBuilder::new(Arc::clone(&rs), UpdateChannels::from(&update_channels_string[..]), Arc::clone(&s)).build();
+        assert_eq!(updater.get_periodicity(), DEFAULT_PERIODICITY);
+        assert_eq!(updater.max_download_bytes, DEFAULT_MAX_DOWNLOAD_BYTES);
+        assert_eq!(updater.default_rulesets, None);
+        assert_eq!(updater.get_user_agent(), DEFAULT_USER_AGENT);
+
+        let updater = UpdaterBuilder::new(rs, UpdateChannels::from(&update_channels_string[..]), s)
+            .default_rulesets(String::from("[]"))
+            .periodicity(60)
+            .max_download_bytes(1024)
+            .jitter_fraction(2.0)
+            .jitter_seed(42)
+            .user_agent(String::from("HTTPS Everywhere/2021.7.13"))
+            .build();
+        assert_eq!(updater.get_periodicity(), 60);
+        assert_eq!(updater.max_download_bytes, 1024);
+        assert_eq!(updater.default_rulesets, Some(String::from("[]")));
+        assert_eq!(updater.jitter_fraction, 1.0);
+        assert_eq!(updater.jitter_seed, 42);
+        assert_eq!(updater.get_user_agent(), "HTTPS Everywhere/2021.7.13");
+    }
+}
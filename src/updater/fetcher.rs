@@ -0,0 +1,230 @@
+use http_req::{request::Request, response::Response, uri::Uri};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// A Fetcher performs the network requests made by the updater.  Embedders which must route
+/// requests through their own networking stack can provide their own implementation via
+/// `Updater::set_fetcher`; by default, requests are made with `http_req`
+pub trait Fetcher: Send + Sync {
+    /// Perform a GET request for the given URL, returning the status code and body of the
+    /// response.  Implementations must return an error rather than a body larger than `max_bytes`
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL to request
+    /// * `max_bytes` - The maximum size of the response body, in bytes
+    fn get(&self, url: &str, max_bytes: usize) -> Result<(u16, Vec<u8>), Box<dyn Error>>;
+
+    /// Perform a conditional GET request for the given URL, sending the validators of a previous
+    /// response so that the server may reply with 304 Not Modified.  Returns the status code,
+    /// body, and validators of the response.  By default, this falls back to an unconditional
+    /// request which returns no validators
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL to request
+    /// * `max_bytes` - The maximum size of the response body, in bytes
+    /// * `validators` - The validators of a previous response for the URL
+    fn get_conditional(&self, url: &str, max_bytes: usize, validators: &CacheValidators) -> Result<(u16, Vec<u8>, CacheValidators), Box<dyn Error>> {
+        let _ = validators;
+        let (status_code, body) = self.get(url, max_bytes)?;
+        Ok((status_code, body, CacheValidators::default()))
+    }
+
+    /// Perform a GET request for the given URL, sending the given User-Agent header.  `Updater`
+    /// makes its requests with this, passing its configured user agent.  By default, the user
+    /// agent is ignored and this falls back to `get`
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL to request
+    /// * `max_bytes` - The maximum size of the response body, in bytes
+    /// * `user_agent` - The value of the User-Agent header
+    fn get_with_user_agent(&self, url: &str, max_bytes: usize, user_agent: &str) -> Result<(u16, Vec<u8>), Box<dyn Error>> {
+        let _ = user_agent;
+        self.get(url, max_bytes)
+    }
+
+    /// Perform a conditional GET request for the given URL, sending the given User-Agent header.
+    /// By default, the user agent is ignored and this falls back to `get_conditional`
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL to request
+    /// * `max_bytes` - The maximum size of the response body, in bytes
+    /// * `validators` - The validators of a previous response for the URL
+    /// * `user_agent` - The value of the User-Agent header
+    fn get_conditional_with_user_agent(&self, url: &str, max_bytes: usize, validators: &CacheValidators, user_agent: &str) -> Result<(u16, Vec<u8>, CacheValidators), Box<dyn Error>> {
+        let _ = user_agent;
+        self.get_conditional(url, max_bytes, validators)
+    }
+}
+
+/// The User-Agent header sent with update requests unless another is set, identifying this crate
+pub const DEFAULT_USER_AGENT: &str = concat!("https-everywhere-lib-core/", env!("CARGO_PKG_VERSION"));
+
+/// The `ETag` and `Last-Modified` headers of a response, used to make conditional requests
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CacheValidators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl CacheValidators {
+    /// Returns true if the response provided no validators
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// The default fetcher, which makes requests with `http_req`, and reads `file://` URLs from the
+/// local filesystem for update channels pointing at a local directory.  Requests made other than
+/// through `Updater` send `DEFAULT_USER_AGENT`
+pub struct HttpReqFetcher;
+
+impl HttpReqFetcher {
+    /// Send a GET request with the User-Agent header and any validators, returning the response
+    /// along with its body
+    fn send(&self, url: &str, max_bytes: usize, validators: &CacheValidators, user_agent: &str) -> Result<(Response, Vec<u8>), Box<dyn Error>> {
+        let uri = Uri::try_from(url)?;
+        let mut request = Request::new(&uri);
+        request.header("User-Agent", user_agent);
+        if let Some(etag) = &validators.etag {
+            request.header("If-None-Match", etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request.header("If-Modified-Since", last_modified);
+        }
+
+        let mut writer = LimitedWriter::new(max_bytes);
+        let res = request.send(&mut writer);
+
+        if writer.exceeded {
+            return Err(Box::new(io::Error::other(format!("The response exceeded the maximum download size of {} bytes", max_bytes))));
+        }
+
+        Ok((res?, writer.buffer))
+    }
+}
+
+impl Fetcher for HttpReqFetcher {
+    fn get(&self, url: &str, max_bytes: usize) -> Result<(u16, Vec<u8>), Box<dyn Error>> {
+        self.get_with_user_agent(url, max_bytes, DEFAULT_USER_AGENT)
+    }
+
+    fn get_conditional(&self, url: &str, max_bytes: usize, validators: &CacheValidators) -> Result<(u16, Vec<u8>, CacheValidators), Box<dyn Error>> {
+        self.get_conditional_with_user_agent(url, max_bytes, validators, DEFAULT_USER_AGENT)
+    }
+
+    fn get_with_user_agent(&self, url: &str, max_bytes: usize, user_agent: &str) -> Result<(u16, Vec<u8>), Box<dyn Error>> {
+        if let Some(path) = file_path(url)? {
+            return read_file(&path, max_bytes);
+        }
+
+        let (res, body) = self.send(url, max_bytes, &CacheValidators::default(), user_agent)?;
+        Ok((res.status_code().into(), body))
+    }
+
+    fn get_conditional_with_user_agent(&self, url: &str, max_bytes: usize, validators: &CacheValidators, user_agent: &str) -> Result<(u16, Vec<u8>, CacheValidators), Box<dyn Error>> {
+        if let Some(path) = file_path(url)? {
+            let (status_code, body) = read_file(&path, max_bytes)?;
+            return Ok((status_code, body, CacheValidators::default()));
+        }
+
+        let (res, body) = self.send(url, max_bytes, validators, user_agent)?;
+        let response_validators = CacheValidators {
+            etag: res.headers().get("ETag").cloned(),
+            last_modified: res.headers().get("Last-Modified").cloned(),
+        };
+        Ok((res.status_code().into(), body, response_validators))
+    }
+}
+
+/// Returns the local path a `file://` URL points to, or `None` for URLs with any other scheme.
+/// Fails for file URLs naming a host other than `localhost`
+///
+/// # Arguments
+///
+/// * `url` - The URL to request
+fn file_path(url: &str) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    if !url.starts_with("file:") {
+        return Ok(None);
+    }
+
+    match Url::parse(url)?.to_file_path() {
+        Ok(path) => Ok(Some(path)),
+        Err(()) => Err(Box::new(io::Error::new(io::ErrorKind::InvalidInput, format!("{} does not point to a local file", url)))),
+    }
+}
+
+/// Read a file from the local filesystem in place of fetching it, for update channels whose update
+/// path prefix is a `file://` URL.  Returns a status code of 404 if the file does not exist, and
+/// at most one byte more than `max_bytes`, so that the caller can tell the file is too large
+///
+/// # Arguments
+///
+/// * `path` - The path of the file
+/// * `max_bytes` - The maximum size of the file, in bytes
+fn read_file(path: &Path, max_bytes: usize) -> Result<(u16, Vec<u8>), Box<dyn Error>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok((404, vec![])),
+        Err(err) => return Err(Box::new(err)),
+    };
+    let mut body = vec![];
+    file.take(max_bytes as u64 + 1).read_to_end(&mut body)?;
+    Ok((200, body))
+}
+
+/// A writer which accumulates bytes in memory, and fails once more than `limit` bytes have been
+/// written to it
+struct LimitedWriter {
+    buffer: Vec<u8>,
+    limit: usize,
+    exceeded: bool,
+}
+
+impl LimitedWriter {
+    fn new(limit: usize) -> LimitedWriter {
+        LimitedWriter {
+            buffer: Vec::new(),
+            limit,
+            exceeded: false,
+        }
+    }
+}
+
+impl Write for LimitedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.buffer.len() + buf.len() > self.limit {
+            self.exceeded = true;
+            return Err(io::Error::other("maximum download size exceeded"));
+        }
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_download_size() {
+        let mut writer = LimitedWriter::new(8);
+        writer.write_all(b"12345").unwrap();
+        assert!(!writer.exceeded);
+
+        assert!(writer.write_all(b"6789").is_err());
+        assert!(writer.exceeded);
+        assert_eq!(writer.buffer, b"12345");
+    }
+}
@@ -0,0 +1,19 @@
+/// Log an event concerning an update channel, with optional fields such as the `action` taken and
+/// the `timestamp` concerned.  With the `tracing` feature, the channel name and fields are emitted
+/// as structured fields of a `tracing` event.  Otherwise, the event is logged with `log`, the
+/// message being prefixed with the channel name and the fields being left out
+///
+/// ```ignore
+/// channel_log!(info, uc.name, action = "no_update", "No new ruleset bundle discovered.");
+/// ```
+macro_rules! channel_log {
+    ($level:ident, $channel:expr, $($field:ident = $value:expr,)* $message:literal $(, $arg:expr)* $(,)?) => {{
+        #[cfg(feature="tracing")]
+        tracing::$level!(channel = %$channel, $($field = %$value,)* $message $(, $arg)*);
+        #[cfg(not(feature="tracing"))]
+        {
+            $(let _ = &$value;)*
+            log::$level!(concat!("{}: ", $message), $channel $(, $arg)*);
+        }
+    }};
+}
@@ -1,5 +1,12 @@
-use openssl::{rsa::Rsa, pkey::Public};
+use openssl::asn1::Asn1Time;
+use openssl::pkey::{Id, PKey, Public};
+use openssl::stack::Stack;
+use openssl::x509::{X509, X509Ref, X509StoreContext};
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::verify::X509VerifyFlags;
 use serde_json::Value;
+use std::error::Error;
+use std::fmt;
 use crate::strings::ERROR_SERDE_PARSE;
 
 struct StaticJsonStrings {
@@ -8,7 +15,11 @@ struct StaticJsonStrings {
     pub update_path_prefix: &'static str,
     pub scope: &'static str,
     pub replaces_default_rulesets: &'static str,
+    pub priority: &'static str,
     pub pem: &'static str,
+    pub pems: &'static str,
+    pub certs: &'static str,
+    pub signature_algorithm: &'static str,
 }
 
 const JSON_STRINGS: StaticJsonStrings = StaticJsonStrings {
@@ -17,7 +28,11 @@ const JSON_STRINGS: StaticJsonStrings = StaticJsonStrings {
     update_path_prefix: "update_path_prefix",
     scope: "scope",
     replaces_default_rulesets: "replaces_default_rulesets",
+    priority: "priority",
     pem: "pem",
+    pems: "pems",
+    certs: "certs",
+    signature_algorithm: "signature_algorithm",
 };
 
 #[derive(Debug)]
@@ -27,17 +42,114 @@ pub enum UpdateChannelFormat {
     Bloom,
 }
 
-/// An UpdateChannel defines where to find ruleset updates, the key to verify them, the scope they
+/// The reasons an update channel specification may fail to parse
+#[derive(Debug)]
+pub enum UpdateChannelError {
+    /// The input is not valid JSON
+    InvalidJson(serde_json::Error),
+    /// The update channels specification is not an array
+    NotAnArray,
+    /// An update channel specification is not an object
+    NotAnObject,
+    /// An update channel has no name
+    MissingName,
+    /// An update channel has no update path prefix
+    MissingUpdatePathPrefix,
+    /// An update channel has an update path prefix which does not use `https://`, and the policy
+    /// does not allow this
+    InsecureUpdatePathPrefix(String),
+    /// An update channel has no pem
+    MissingPem,
+    /// An update channel has no certificate chain, and the policy requires one
+    MissingCertificate,
+    /// A certificate could not be parsed
+    InvalidCertificate,
+    /// A certificate chain does not chain to the trusted root of the policy, a certificate within
+    /// it is not currently valid, or one which issues another is not a certificate authority
+    UntrustedCertificate,
+    /// A pem could not be parsed into a public key
+    InvalidPem,
+    /// A public key can not be used with the signature algorithm of the update channel
+    KeySignatureAlgorithmMismatch,
+    /// The signature algorithm of an update channel is not recognized
+    UnknownSignatureAlgorithm(String),
+}
+
+impl fmt::Display for UpdateChannelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UpdateChannelError::InvalidJson(err) => write!(f, "Could not parse update channel JSON: {}", err),
+            UpdateChannelError::NotAnArray => write!(f, "Unexpected: update channels is not an array"),
+            UpdateChannelError::NotAnObject => write!(f, "Unexpected: update channel is not an object"),
+            UpdateChannelError::MissingName => write!(f, "Name can not be blank"),
+            UpdateChannelError::MissingUpdatePathPrefix => write!(f, "Update path prefix can not be blank"),
+            UpdateChannelError::InsecureUpdatePathPrefix(prefix) => write!(f, "Update path prefix does not use https: {}", prefix),
+            UpdateChannelError::MissingPem => write!(f, "Pem can not be blank"),
+            UpdateChannelError::InvalidPem => write!(f, "Could not parse public key"),
+            UpdateChannelError::MissingCertificate => write!(f, "Certificate chain can not be blank when a trusted root is given"),
+            UpdateChannelError::InvalidCertificate => write!(f, "Could not parse certificate"),
+            UpdateChannelError::UntrustedCertificate => write!(f, "Certificate chain is not valid for the trusted root"),
+            UpdateChannelError::KeySignatureAlgorithmMismatch => write!(f, "Public key does not match the signature algorithm"),
+            UpdateChannelError::UnknownSignatureAlgorithm(algorithm) => write!(f, "Unknown signature algorithm: {}", algorithm),
+        }
+    }
+}
+
+impl Error for UpdateChannelError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            UpdateChannelError::InvalidJson(err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+/// A policy for which update channels are acceptable.  The default policy requires that updates
+/// are fetched over TLS
+#[derive(Debug)]
+#[derive(Default)]
+#[derive(Clone)]
+pub struct UpdateChannelPolicy {
+    /// Allow update path prefixes which do not use `https://`, for instance `file://` prefixes
+    /// pointing at a local directory of rulesets for testing
+    pub allow_insecure_update_path_prefix: bool,
+    /// A root public key which every update channel must chain to.  When given, each update
+    /// channel specifies a certificate chain rather than raw pems, and its key is taken from the
+    /// first certificate of the chain once the chain has been validated against the root.  By
+    /// default there is no trusted root, and keys are taken from raw pems
+    pub trusted_root: Option<PKey<Public>>,
+}
+
+/// The algorithm used to sign the updates published on an update channel
+#[derive(Debug)]
+#[derive(PartialEq)]
+#[derive(Clone, Copy)]
+pub enum SignatureAlgorithm {
+    /// RSA PSS with SHA-256, specified as `rsa-pss-sha256`.  This is the default
+    RsaPssSha256,
+    /// Ed25519, specified as `ed25519`
+    Ed25519,
+}
+
+/// An UpdateChannel defines where to find ruleset updates, the keys to verify them, the scope they
 /// are applied to (which should be a regular expression), and whether they replace the default
-/// rulesets included with the application.
+/// rulesets included with the application.  An update is accepted if its signature verifies
+/// against any of the keys, which allows keys to be rotated.
 #[derive(Debug)]
 pub struct UpdateChannel {
     pub name: String,
     pub format: UpdateChannelFormat,
-    pub key: Rsa<Public>,
+    pub signature_algorithm: SignatureAlgorithm,
+    pub keys: Vec<PKey<Public>>,
     pub update_path_prefix: String,
     pub scope: Option<String>,
     pub replaces_default_rulesets: bool,
+    /// The priority of the rulesets of this update channel over those of others which apply to
+    /// the same host.  Defaults to 0
+    pub priority: i32,
+    /// The certificate chain the key was taken from, leaf first, if the update channel was
+    /// validated against a trusted root.  Empty for update channels with raw pems
+    pub certificates: Vec<X509>,
 }
 
 impl From<&str> for UpdateChannel {
@@ -46,12 +158,17 @@ impl From<&str> for UpdateChannel {
     /// # Arguments
     ///
     /// * `json_string` - A json string specifying the update channel.  See
-    /// [`tests/update_channels.json`](https://github.com/EFForg/https-everywhere-lib-core/blob/master/tests/update_channels.json) for the correct format
+    ///   [`tests/update_channels.json`](https://github.com/EFForg/https-everywhere-lib-core/blob/master/tests/update_channels.json) for the correct format.
+    ///   Keys are given as a pem string or an array of pem strings, in the `pem` or `pems` field.
+    ///   Under a policy with a trusted root, a certificate chain is instead given as a pem string
+    ///   or an array of pem strings, leaf first, in the `certs` field
     ///
     /// # Panics
     ///
-    /// Panics if a name, update path prefix, or pem is not specified, if the pem file does not
-    /// parse correctly into an RSA key, or it is not an object
+    /// Panics if a name, update path prefix, or pem is not specified, if the update path prefix
+    /// does not use `https://`, if the signature algorithm
+    /// is not `rsa-pss-sha256` or `ed25519`, if the pem file does not parse correctly into a key
+    /// for that algorithm, or it is not an object
     fn from(json_string: &str) -> UpdateChannel {
         let update_channel: Value = serde_json::from_str(&json_string).expect(ERROR_SERDE_PARSE);
         UpdateChannel::from(&update_channel)
@@ -63,10 +180,43 @@ impl From<&Value> for UpdateChannel {
     ///
     /// See the implementation of `From<&str>` for more detail
     fn from(json_value: &Value) -> UpdateChannel {
+        match UpdateChannel::try_from_value(json_value) {
+            Ok(update_channel) => update_channel,
+            Err(err) => panic!("{}", err)
+        }
+    }
+}
+
+impl UpdateChannel {
+    /// Returns an update channel given a JSON string, or the reason it could not be parsed
+    ///
+    /// See the implementation of `From<&str>` for the conditions which are considered errors
+    pub fn try_from_json(json_string: &str) -> Result<UpdateChannel, UpdateChannelError> {
+        let update_channel: Value = serde_json::from_str(json_string).map_err(UpdateChannelError::InvalidJson)?;
+        UpdateChannel::try_from_value(&update_channel)
+    }
+
+    /// Returns an update channel given a serde_json::Value, or the reason it could not be parsed.
+    /// `From<&Value>` can not be paired with `TryFrom<&Value>`, since the latter is
+    /// implemented automatically in terms of the former
+    ///
+    /// See the implementation of `From<&str>` for the conditions which are considered errors
+    pub fn try_from_value(json_value: &Value) -> Result<UpdateChannel, UpdateChannelError> {
+        UpdateChannel::try_from_value_with_policy(json_value, &UpdateChannelPolicy::default())
+    }
+
+    /// Returns an update channel given a serde_json::Value, or the reason it could not be parsed
+    /// or is not acceptable under the given policy
+    ///
+    /// # Arguments
+    ///
+    /// * `json_value` - A serde_json::Value specifying the update channel
+    /// * `policy` - The policy the update channel must conform to
+    pub fn try_from_value_with_policy(json_value: &Value, policy: &UpdateChannelPolicy) -> Result<UpdateChannel, UpdateChannelError> {
         if let Value::Object(update_channel) = json_value {
             let name = match update_channel.get(JSON_STRINGS.name) {
                 Some(Value::String(name)) => name.to_string(),
-                _ => panic!("Name can not be blank")
+                _ => return Err(UpdateChannelError::MissingName)
             };
             let format = match update_channel.get(JSON_STRINGS.format) {
                 Some(Value::String(format)) => {
@@ -80,10 +230,17 @@ impl From<&Value> for UpdateChannel {
             };
             let update_path_prefix = match update_channel.get(JSON_STRINGS.update_path_prefix) {
                 Some(Value::String(update_path_prefix)) => update_path_prefix.to_string(),
-                _ => panic!("Update path prefix can not be blank")
+                _ => return Err(UpdateChannelError::MissingUpdatePathPrefix)
             };
+            if !update_path_prefix.to_lowercase().starts_with("https://") {
+                if policy.allow_insecure_update_path_prefix {
+                    channel_log!(warn, name, "Update path prefix does not use https: {}", update_path_prefix);
+                } else {
+                    return Err(UpdateChannelError::InsecureUpdatePathPrefix(update_path_prefix));
+                }
+            }
             let scope = match update_channel.get(JSON_STRINGS.scope) {
-                Some(Value::String(scope)) if scope == "" => None,
+                Some(Value::String(scope)) if scope.is_empty() => None,
                 Some(Value::String(scope)) => Some(scope.to_string()),
                 _ => None
             };
@@ -91,32 +248,136 @@ impl From<&Value> for UpdateChannel {
                 Some(Value::Bool(replaces_default_rulesets)) => *replaces_default_rulesets,
                 _ => false
             };
-            let key = match update_channel.get(JSON_STRINGS.pem) {
-                Some(Value::String(pem)) => {
-                    match Rsa::public_key_from_pem(&pem.clone().into_bytes()) {
-                        Ok(key) => key,
-                        _ => panic!("Could not parse public key")
+            let priority = match update_channel.get(JSON_STRINGS.priority).and_then(Value::as_i64) {
+                Some(priority) => priority.clamp(i32::MIN as i64, i32::MAX as i64) as i32,
+                None => 0
+            };
+            let signature_algorithm = match update_channel.get(JSON_STRINGS.signature_algorithm) {
+                Some(Value::String(algorithm)) if algorithm == "ed25519" => SignatureAlgorithm::Ed25519,
+                Some(Value::String(algorithm)) if algorithm == "rsa-pss-sha256" => SignatureAlgorithm::RsaPssSha256,
+                None => SignatureAlgorithm::RsaPssSha256,
+                Some(Value::String(algorithm)) => return Err(UpdateChannelError::UnknownSignatureAlgorithm(algorithm.to_string())),
+                Some(algorithm) => return Err(UpdateChannelError::UnknownSignatureAlgorithm(algorithm.to_string()))
+            };
+            let (keys, certificates) = match &policy.trusted_root {
+                Some(trusted_root) => {
+                    let mut certificates = vec![];
+                    for pem in pem_strings(update_channel.get(JSON_STRINGS.certs)).ok_or(UpdateChannelError::InvalidCertificate)? {
+                        certificates.extend(X509::stack_from_pem(pem.as_bytes()).map_err(|_| UpdateChannelError::InvalidCertificate)?);
+                    }
+                    if certificates.is_empty() {
+                        return Err(UpdateChannelError::MissingCertificate);
                     }
+                    verify_certificate_chain(&certificates, trusted_root)?;
+                    let key = certificates[0].public_key().map_err(|_| UpdateChannelError::InvalidCertificate)?;
+                    (vec![check_key_algorithm(key, signature_algorithm)?], certificates)
+                },
+                None => {
+                    let mut pems = vec![];
+                    for field in &[JSON_STRINGS.pem, JSON_STRINGS.pems] {
+                        pems.extend(pem_strings(update_channel.get(*field)).ok_or(UpdateChannelError::InvalidPem)?);
+                    }
+                    if pems.is_empty() {
+                        return Err(UpdateChannelError::MissingPem);
+                    }
+                    let keys = pems.into_iter()
+                        .map(|pem| parse_public_key(pem, signature_algorithm))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    (keys, vec![])
                 },
-                _ => panic!("Pem can not be blank")
             };
-            UpdateChannel {
+            Ok(UpdateChannel {
                 name,
                 format,
-                key,
+                signature_algorithm,
+                keys,
                 update_path_prefix,
                 scope,
                 replaces_default_rulesets,
-            }
+                priority,
+                certificates,
+            })
         } else {
-            panic!("Unexpected: update channel is not an object");
+            Err(UpdateChannelError::NotAnObject)
         }
     }
+
+    /// Returns whether any certificate of the chain the key was taken from has expired, or is not
+    /// yet valid.  Always false for update channels with raw pems
+    pub fn has_invalid_certificates(&self) -> bool {
+        !self.certificates.iter().all(|certificate| is_currently_valid(certificate))
+    }
+}
+
+/// Returns the pem strings of a field given as a string or an array of strings, or `None` if the
+/// array contains anything other than strings
+fn pem_strings(field: Option<&Value>) -> Option<Vec<&str>> {
+    match field {
+        Some(Value::String(pem)) => Some(vec![pem]),
+        Some(Value::Array(pem_array)) => pem_array.iter().map(Value::as_str).collect(),
+        _ => Some(vec![])
+    }
+}
+
+/// Returns whether the current time is within the validity period of a certificate
+fn is_currently_valid(certificate: &X509Ref) -> bool {
+    match Asn1Time::days_from_now(0) {
+        Ok(now) => certificate.not_before() <= now && certificate.not_after() >= now,
+        Err(_) => false
+    }
+}
+
+/// Ensure that a certificate chain, given leaf first, is valid for the trusted root.  The last
+/// certificate must be signed by the root, and is then used as the trust anchor to verify the rest
+/// of the chain with OpenSSL, which requires every certificate issuing another to be a certificate
+/// authority allowed to sign certificates, issuer and subject names to match, and path length
+/// constraints to hold.  Every certificate must also be currently valid
+fn verify_certificate_chain(certificates: &[X509], trusted_root: &PKey<Public>) -> Result<(), UpdateChannelError> {
+    let (anchor, _) = certificates.split_last().ok_or(UpdateChannelError::MissingCertificate)?;
+    if !anchor.verify(trusted_root).unwrap_or(false) || !certificates.iter().all(|certificate| is_currently_valid(certificate)) {
+        return Err(UpdateChannelError::UntrustedCertificate);
+    }
+
+    let verified = (|| {
+        let mut store = X509StoreBuilder::new()?;
+        store.add_cert(anchor.clone())?;
+        store.set_flags(X509VerifyFlags::PARTIAL_CHAIN)?;
+        let store = store.build();
+        let mut chain = Stack::new()?;
+        for certificate in &certificates[1..] {
+            chain.push(certificate.clone())?;
+        }
+        X509StoreContext::new()?.init(&store, &certificates[0], &chain, |context| context.verify_cert())
+    })();
+    match verified {
+        Ok(true) => Ok(()),
+        _ => Err(UpdateChannelError::UntrustedCertificate),
+    }
+}
+
+/// Parse a PEM-encoded public key, ensuring that it can be used with the given signature algorithm
+fn parse_public_key(pem: &str, signature_algorithm: SignatureAlgorithm) -> Result<PKey<Public>, UpdateChannelError> {
+    match PKey::public_key_from_pem(pem.as_bytes()) {
+        Ok(key) => check_key_algorithm(key, signature_algorithm),
+        _ => Err(UpdateChannelError::InvalidPem)
+    }
 }
 
+/// Ensure that a public key can be used with the given signature algorithm
+fn check_key_algorithm(key: PKey<Public>, signature_algorithm: SignatureAlgorithm) -> Result<PKey<Public>, UpdateChannelError> {
+    let expected_key_id = match signature_algorithm {
+        SignatureAlgorithm::RsaPssSha256 => Id::RSA,
+        SignatureAlgorithm::Ed25519 => Id::ED25519,
+    };
+    if key.id() != expected_key_id {
+        return Err(UpdateChannelError::KeySignatureAlgorithmMismatch);
+    }
+    Ok(key)
+}
 
-/// RuleSets consists of a tuple vec of update channels
+/// UpdateChannels consists of a tuple vec of update channels
 #[derive(Debug)]
+#[derive(Default)]
 pub struct UpdateChannels(Vec<UpdateChannel>);
 
 impl UpdateChannels {
@@ -129,6 +390,50 @@ impl UpdateChannels {
     pub fn get_all_mut(&mut self) -> &mut Vec<UpdateChannel>{
        &mut self.0
     }
+
+    /// Returns update channels given a JSON string, or the reason they could not be parsed
+    ///
+    /// See `UpdateChannel::try_from_value` for more detail
+    pub fn try_from_json(json_string: &str) -> Result<UpdateChannels, UpdateChannelError> {
+        let update_channels: Value = serde_json::from_str(json_string).map_err(UpdateChannelError::InvalidJson)?;
+        UpdateChannels::try_from_value(&update_channels)
+    }
+
+    /// Returns update channels given a serde_json::Value, or the reason they could not be parsed
+    ///
+    /// See `UpdateChannel::try_from_value` for more detail
+    pub fn try_from_value(json_value: &Value) -> Result<UpdateChannels, UpdateChannelError> {
+        UpdateChannels::try_from_value_with_policy(json_value, &UpdateChannelPolicy::default())
+    }
+
+    /// Returns update channels given a JSON string, or the reason they could not be parsed or are
+    /// not acceptable under the given policy
+    ///
+    /// # Arguments
+    ///
+    /// * `json_string` - A json string specifying the update channels
+    /// * `policy` - The policy the update channels must conform to
+    pub fn try_from_json_with_policy(json_string: &str, policy: &UpdateChannelPolicy) -> Result<UpdateChannels, UpdateChannelError> {
+        let update_channels: Value = serde_json::from_str(json_string).map_err(UpdateChannelError::InvalidJson)?;
+        UpdateChannels::try_from_value_with_policy(&update_channels, policy)
+    }
+
+    /// Returns update channels given a serde_json::Value, or the reason they could not be parsed
+    /// or are not acceptable under the given policy
+    ///
+    /// # Arguments
+    ///
+    /// * `json_value` - A serde_json::Value specifying the update channels
+    /// * `policy` - The policy the update channels must conform to
+    pub fn try_from_value_with_policy(json_value: &Value, policy: &UpdateChannelPolicy) -> Result<UpdateChannels, UpdateChannelError> {
+        if let Value::Array(update_channels) = json_value {
+            Ok(UpdateChannels(update_channels.iter()
+                .map(|update_channel| UpdateChannel::try_from_value_with_policy(update_channel, policy))
+                .collect::<Result<Vec<_>, _>>()?))
+        } else {
+            Err(UpdateChannelError::NotAnArray)
+        }
+    }
 }
 
 /// Returns update channels given a JSON string
@@ -140,21 +445,67 @@ impl UpdateChannels {
 /// Panics if the update channels JSON is not an array
 impl From<&str> for UpdateChannels {
     fn from(json_string: &str) -> UpdateChannels {
-        if let Value::Array(update_channels) = serde_json::from_str(&json_string).expect(ERROR_SERDE_PARSE) {
-            UpdateChannels(update_channels.into_iter().map(|uc| {
-                UpdateChannel::from(&uc)
-            }).collect())
-        } else {
-            panic!("Unexpected: update channels is not an array")
+        let update_channels: Value = serde_json::from_str(json_string).expect(ERROR_SERDE_PARSE);
+        UpdateChannels::from(&update_channels)
+    }
+}
+
+/// Returns update channels given a serde_json::Value
+///
+/// See the implementation of `From<&str> for UpdateChannel` for more detail
+///
+/// # Panics
+///
+/// Panics if the update channels value is not an array, or any update channel within it is invalid
+impl From<&Value> for UpdateChannels {
+    fn from(json_value: &Value) -> UpdateChannels {
+        match UpdateChannels::try_from_value(json_value) {
+            Ok(update_channels) => update_channels,
+            Err(err) => panic!("{}", err)
         }
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub mod tests {
     use super::*;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::Private;
+    use openssl::rsa::Rsa;
+    use openssl::x509::X509NameBuilder;
+    use openssl::x509::extension::BasicConstraints;
     use std::fs;
 
+    /// Returns a certificate for the key given, signed by the issuer key and valid for the given
+    /// number of days from now, or one which has already expired if `None`.  Each key is named
+    /// after its fingerprint, so that certificates issued by different keys chain by name.  If
+    /// `ca` is true, the certificate is marked as a certificate authority
+    pub fn issue_certificate(key: &PKey<Private>, issuer_key: &PKey<Private>, days_valid: Option<u32>, ca: bool) -> X509 {
+        let name = |key: &PKey<Private>| {
+            let fingerprint = openssl::sha::sha256(&key.public_key_to_der().unwrap());
+            let mut name = X509NameBuilder::new().unwrap();
+            let fingerprint: String = fingerprint[..8].iter().map(|byte| format!("{:02x}", byte)).collect();
+            name.append_entry_by_text("CN", &format!("Update Channel Test {}", fingerprint)).unwrap();
+            name.build()
+        };
+        let (not_before, not_after) = match days_valid {
+            Some(days_valid) => (Asn1Time::days_from_now(0).unwrap(), Asn1Time::days_from_now(days_valid).unwrap()),
+            None => (Asn1Time::from_unix(0).unwrap(), Asn1Time::from_unix(86400).unwrap()),
+        };
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name(key)).unwrap();
+        builder.set_issuer_name(&name(issuer_key)).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder.set_not_before(&not_before).unwrap();
+        builder.set_not_after(&not_after).unwrap();
+        if ca {
+            builder.append_extension(BasicConstraints::new().critical().ca().build().unwrap()).unwrap();
+        }
+        builder.sign(issuer_key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
     fn mock_update_channels_json() -> String {
         fs::read_to_string("tests/update_channels.json").unwrap()
     }
@@ -171,6 +522,69 @@ mod tests {
         assert_eq!(format!("{:?}", ucs), update_channels_representation);
     }
 
+    #[test]
+    fn creates_update_channels_from_value() {
+        let update_channels: Value = serde_json::from_str(&mock_update_channels_json()).expect(ERROR_SERDE_PARSE);
+        let ucs = UpdateChannels::from(&update_channels);
+        assert_eq!(format!("{:?}", ucs), format!("{:?}", create_mock_update_channels()));
+
+        assert!(UpdateChannels::try_from_value(&update_channels).is_ok());
+    }
+
+    #[test]
+    fn defaults_to_no_update_channels() {
+        assert!(UpdateChannels::default().get_all().is_empty());
+    }
+
+    #[test]
+    fn fails_to_create_update_channels_from_invalid_value() {
+        let mut update_channels: Value = serde_json::from_str(&mock_update_channels_json()).expect(ERROR_SERDE_PARSE);
+        update_channels.get_mut(1).unwrap().get_mut(JSON_STRINGS.name).unwrap().take();
+        match UpdateChannels::try_from_value(&update_channels) {
+            Err(UpdateChannelError::MissingName) => {},
+            other => panic!("Unexpected result: {:?}", other)
+        }
+
+        match UpdateChannels::try_from_value(&Value::Bool(true)) {
+            Err(UpdateChannelError::NotAnArray) => {},
+            other => panic!("Unexpected result: {:?}", other)
+        }
+    }
+
+    #[test]
+    fn fails_to_create_update_channels_from_invalid_json() {
+        assert!(UpdateChannels::try_from_json(&mock_update_channels_json()).is_ok());
+
+        match UpdateChannels::try_from_json("[{") {
+            Err(UpdateChannelError::InvalidJson(_)) => {},
+            other => panic!("Unexpected result: {:?}", other)
+        }
+
+        let mut update_channels: Value = serde_json::from_str(&mock_update_channels_json()).expect(ERROR_SERDE_PARSE);
+        let update_channel = update_channels.get_mut(0).unwrap().as_object_mut().unwrap();
+        update_channel.insert(String::from(JSON_STRINGS.signature_algorithm), Value::String(String::from("md5")));
+        match UpdateChannel::try_from_json(&update_channels[0].to_string()) {
+            Err(UpdateChannelError::UnknownSignatureAlgorithm(algorithm)) => assert_eq!(algorithm, "md5"),
+            other => panic!("Unexpected result: {:?}", other)
+        }
+    }
+
+    #[test]
+    fn requires_https_update_path_prefix_by_default() {
+        let mut update_channels: Value = serde_json::from_str(&mock_update_channels_json()).expect(ERROR_SERDE_PARSE);
+        let update_channel = update_channels.get_mut(0).unwrap().as_object_mut().unwrap();
+        update_channel.insert(String::from(JSON_STRINGS.update_path_prefix), Value::String(String::from("http://localhost:8000/")));
+
+        match UpdateChannels::try_from_value(&update_channels) {
+            Err(UpdateChannelError::InsecureUpdatePathPrefix(prefix)) => assert_eq!(prefix, "http://localhost:8000/"),
+            other => panic!("Unexpected result: {:?}", other)
+        }
+
+        let policy = UpdateChannelPolicy { allow_insecure_update_path_prefix: true, ..Default::default() };
+        let ucs = UpdateChannels::try_from_value_with_policy(&update_channels, &policy).unwrap();
+        assert_eq!(ucs.get_all()[0].update_path_prefix, "http://localhost:8000/");
+    }
+
     #[test]
     #[should_panic]
     fn panics_if_no_name_specified() {
@@ -195,6 +609,125 @@ mod tests {
         UpdateChannel::from(update_channels.get(0).unwrap());
     }
 
+    #[test]
+    fn parses_priority() {
+        let mut update_channels: Value = serde_json::from_str(&mock_update_channels_json()).expect(ERROR_SERDE_PARSE);
+        assert_eq!(UpdateChannel::from(update_channels.get(0).unwrap()).priority, 0);
+
+        let update_channel = update_channels.get_mut(0).unwrap().as_object_mut().unwrap();
+        update_channel.insert(String::from(JSON_STRINGS.priority), Value::from(-5));
+        assert_eq!(UpdateChannel::from(update_channels.get(0).unwrap()).priority, -5);
+    }
+
+    #[test]
+    fn parses_ed25519_channels() {
+        let mut update_channels: Value = serde_json::from_str(&mock_update_channels_json()).expect(ERROR_SERDE_PARSE);
+        let key = PKey::generate_ed25519().unwrap();
+        let pem = String::from_utf8(key.public_key_to_pem().unwrap()).unwrap();
+        let update_channel = update_channels.get_mut(0).unwrap().as_object_mut().unwrap();
+        update_channel.insert(String::from(JSON_STRINGS.pem), Value::String(pem));
+        update_channel.insert(String::from(JSON_STRINGS.signature_algorithm), Value::String(String::from("ed25519")));
+
+        let uc = UpdateChannel::from(update_channels.get(0).unwrap());
+        assert_eq!(uc.signature_algorithm, SignatureAlgorithm::Ed25519);
+        assert_eq!(uc.keys[0].id(), Id::ED25519);
+    }
+
+    #[test]
+    fn parses_multiple_keys() {
+        let mut update_channels: Value = serde_json::from_str(&mock_update_channels_json()).expect(ERROR_SERDE_PARSE);
+        let second_pem = update_channels[1][JSON_STRINGS.pem].clone();
+        let update_channel = update_channels.get_mut(0).unwrap().as_object_mut().unwrap();
+        update_channel.insert(String::from(JSON_STRINGS.pems), Value::Array(vec![second_pem.clone(), second_pem]));
+
+        let uc = UpdateChannel::from(update_channels.get(0).unwrap());
+        assert_eq!(uc.keys.len(), 3);
+
+        let update_channel = update_channels.get_mut(0).unwrap().as_object_mut().unwrap();
+        let first_pem = update_channel.remove(JSON_STRINGS.pem).unwrap();
+        update_channel.insert(String::from(JSON_STRINGS.pem), Value::Array(vec![first_pem]));
+        update_channel.remove(JSON_STRINGS.pems);
+
+        let uc = UpdateChannel::from(update_channels.get(0).unwrap());
+        assert_eq!(uc.keys.len(), 1);
+    }
+
+    #[test]
+    fn validates_certificate_chains_against_trusted_root() {
+        let root = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let intermediate = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let leaf = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let pem = |certificate: X509| Value::String(String::from_utf8(certificate.to_pem().unwrap()).unwrap());
+        let leaf_pem = pem(issue_certificate(&leaf, &intermediate, Some(30), false));
+        let intermediate_pem = pem(issue_certificate(&intermediate, &root, Some(30), true));
+        let policy = UpdateChannelPolicy {
+            trusted_root: Some(PKey::public_key_from_pem(&root.public_key_to_pem().unwrap()).unwrap()),
+            ..Default::default()
+        };
+
+        let mut update_channels: Value = serde_json::from_str(&mock_update_channels_json()).expect(ERROR_SERDE_PARSE);
+        match UpdateChannel::try_from_value_with_policy(&update_channels[0], &policy) {
+            Err(UpdateChannelError::MissingCertificate) => {},
+            other => panic!("Unexpected result: {:?}", other)
+        }
+
+        let update_channel = update_channels.get_mut(0).unwrap().as_object_mut().unwrap();
+        update_channel.insert(String::from(JSON_STRINGS.certs), Value::Array(vec![leaf_pem.clone(), intermediate_pem.clone()]));
+        let uc = UpdateChannel::try_from_value_with_policy(&update_channels[0], &policy).unwrap();
+        assert_eq!(uc.keys.len(), 1);
+        assert!(uc.keys[0].public_eq(&leaf));
+        assert_eq!(uc.certificates.len(), 2);
+        assert!(!uc.has_invalid_certificates());
+
+        // Without a trusted root, the certificate chain is ignored in favor of the raw pems
+        let uc = UpdateChannel::try_from_value(&update_channels[0]).unwrap();
+        assert!(!uc.keys[0].public_eq(&leaf));
+        assert!(uc.certificates.is_empty());
+
+        let update_channel = update_channels.get_mut(0).unwrap().as_object_mut().unwrap();
+        update_channel.insert(String::from(JSON_STRINGS.certs), leaf_pem.clone());
+        match UpdateChannel::try_from_value_with_policy(&update_channels[0], &policy) {
+            Err(UpdateChannelError::UntrustedCertificate) => {},
+            other => panic!("Unexpected result: {:?}", other)
+        }
+
+        let update_channel = update_channels.get_mut(0).unwrap().as_object_mut().unwrap();
+        update_channel.insert(String::from(JSON_STRINGS.certs), Value::Array(vec![pem(issue_certificate(&leaf, &intermediate, None, false)), intermediate_pem.clone()]));
+        match UpdateChannel::try_from_value_with_policy(&update_channels[0], &policy) {
+            Err(UpdateChannelError::UntrustedCertificate) => {},
+            other => panic!("Unexpected result: {:?}", other)
+        }
+
+        // A certificate which is not a certificate authority can't issue others, whether or not
+        // it was itself issued by the root
+        let other = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        for chain in [
+            vec![pem(issue_certificate(&other, &leaf, Some(30), false)), leaf_pem.clone(), intermediate_pem],
+            vec![pem(issue_certificate(&other, &leaf, Some(30), false)), pem(issue_certificate(&leaf, &root, Some(30), false))],
+        ] {
+            let update_channel = update_channels.get_mut(0).unwrap().as_object_mut().unwrap();
+            update_channel.insert(String::from(JSON_STRINGS.certs), Value::Array(chain));
+            match UpdateChannel::try_from_value_with_policy(&update_channels[0], &policy) {
+                Err(UpdateChannelError::UntrustedCertificate) => {},
+                other => panic!("Unexpected result: {:?}", other)
+            }
+        }
+
+        let update_channel = update_channels.get_mut(0).unwrap().as_object_mut().unwrap();
+        update_channel.insert(String::from(JSON_STRINGS.certs), pem(issue_certificate(&leaf, &root, Some(30), false)));
+        let uc = UpdateChannel::try_from_value_with_policy(&update_channels[0], &policy).unwrap();
+        assert!(uc.keys[0].public_eq(&leaf));
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_if_key_does_not_match_signature_algorithm() {
+        let mut update_channels: Value = serde_json::from_str(&mock_update_channels_json()).expect(ERROR_SERDE_PARSE);
+        let update_channel = update_channels.get_mut(0).unwrap().as_object_mut().unwrap();
+        update_channel.insert(String::from(JSON_STRINGS.signature_algorithm), Value::String(String::from("ed25519")));
+        let _ = UpdateChannel::from(update_channels.get(0).unwrap());
+    }
+
     #[test]
     #[should_panic]
     fn panics_if_pem_specified_incorrectly() {
@@ -0,0 +1,116 @@
+//! Bindings for rewriting URLs from JavaScript, for running the rewriter in a WebAssembly sandbox
+//! such as that of a browser extension.  The crate should be built for `wasm32` with
+//! `--no-default-features --features wasm`, since the updater does not build for `wasm32`, and as
+//! a `cdylib`, from which `wasm-bindgen` emits the JavaScript glue:
+//!
+//! ```sh
+//! cargo rustc --release --target wasm32-unknown-unknown --no-default-features --features wasm --crate-type cdylib
+//! wasm-bindgen --out-dir pkg target/wasm32-unknown-unknown/release/https_everywhere_lib_core.wasm
+//! ```
+//!
+//! ```js
+//! const rulesets = new RuleSets();
+//! rulesets.add_all_from_json_string(rulesetsJson, false);
+//! const rewriter = new Rewriter(rulesets);
+//! const { action, url } = rewriter.rewrite_url("http://example.com/");
+//! ```
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+
+use crate::{RuleSets, Rewriter, rulesets::shared};
+
+/// A set of rulesets to rewrite URLs with, exported to JavaScript as `RuleSets`
+#[wasm_bindgen(js_name = RuleSets)]
+#[derive(Default)]
+pub struct JsRuleSets {
+    rulesets: RuleSets,
+}
+
+#[wasm_bindgen(js_class = RuleSets)]
+impl JsRuleSets {
+    /// Returns an empty set of rulesets
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> JsRuleSets {
+        JsRuleSets::default()
+    }
+
+    /// Construct and add new rulesets given a json string of values, throwing an error if the
+    /// json string is not valid
+    ///
+    /// # Arguments
+    ///
+    /// * `json_string` - A json string representing the rulesets to add
+    /// * `enable_mixed_rulesets` - Whether mixed-content rulesets are active
+    pub fn add_all_from_json_string(&mut self, json_string: &str, enable_mixed_rulesets: bool) -> Result<(), JsValue> {
+        self.rulesets.try_add_all_from_json_string(json_string, enable_mixed_rulesets, &HashMap::new(), &None)
+            .map_err(|err| JsValue::from_str(&err.to_string()))
+    }
+
+    /// Returns the number of targets the rulesets are indexed by
+    pub fn count_targets(&self) -> usize {
+        self.rulesets.count_targets()
+    }
+}
+
+/// A stateless rewriter, exported to JavaScript as `Rewriter`
+#[wasm_bindgen(js_name = Rewriter)]
+pub struct JsRewriter {
+    rewriter: Rewriter,
+}
+
+#[wasm_bindgen(js_class = Rewriter)]
+impl JsRewriter {
+    /// Returns a stateless rewriter with the rulesets specified, which behaves as if HTTPS
+    /// Everywhere is enabled, EASE mode is off, and no sites are disabled.  The rulesets are
+    /// moved into the rewriter, so they can no longer be used from JavaScript
+    ///
+    /// # Arguments
+    ///
+    /// * `rulesets` - The rulesets to rewrite URLs with
+    #[wasm_bindgen(constructor)]
+    pub fn new(rulesets: JsRuleSets) -> JsRewriter {
+        JsRewriter {
+            rewriter: Rewriter::new_stateless(shared::new(rulesets.rulesets)),
+        }
+    }
+
+    /// Returns the action to take for a URL, as an object with the name of the `action` and the
+    /// `url` it carries, if any.  Throws an error if the URL could not be rewritten
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - A URL to determine the action for
+    pub fn rewrite_url(&mut self, url: &str) -> Result<JsValue, JsValue> {
+        let action = self.rewrite_url_json(url).map_err(|err| JsValue::from_str(&err))?;
+        js_sys::JSON::parse(&action.to_string())
+    }
+
+    fn rewrite_url_json(&mut self, url: &str) -> Result<Value, String> {
+        let action = self.rewriter.rewrite_url(url).map_err(|err| err.to_string())?;
+        let (action, url) = action.name_and_url();
+        Ok(json!({
+            "action": action,
+            "url": url,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn rewrites_url_as_json() {
+        let mut rulesets = JsRuleSets::new();
+        rulesets.rulesets.add_all_from_json_string(&fs::read_to_string("tests/mock_rulesets.json").unwrap(), true, &HashMap::new(), &None);
+        let mut rewriter = JsRewriter::new(rulesets);
+
+        assert_eq!(rewriter.rewrite_url_json("http://freerangekitten.com/").unwrap(),
+            json!({"action": "RewriteUrl", "url": "https://freerangekitten.com/"}));
+        assert_eq!(rewriter.rewrite_url_json("http://fake-example.com/").unwrap(),
+            json!({"action": "NoOp", "url": "http://fake-example.com/"}));
+    }
+}
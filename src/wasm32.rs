@@ -0,0 +1,30 @@
+//! A compile test for `wasm32`, which references the APIs that must build without the updater.
+//! Building the crate for `wasm32-unknown-unknown` with any of the `rewriter`, `settings` and
+//! `add_rulesets` features type-checks these references, without needing to run anything
+
+use crate::RuleSets;
+#[cfg(feature="add_rulesets")]
+use std::collections::HashMap;
+#[cfg(feature="rewriter")]
+use crate::Rewriter;
+#[cfg(feature="settings")]
+use crate::Settings;
+
+const _: fn(&mut RuleSets) = |rulesets| {
+    #[cfg(feature="add_rulesets")]
+    rulesets.add_all_from_json_string("[]", false, &HashMap::new(), &None);
+    #[cfg(feature="potentially_applicable")]
+    rulesets.potentially_applicable("example.com");
+    rulesets.count_targets();
+};
+
+#[cfg(feature="settings")]
+const _: fn(&mut Settings) = |settings| {
+    settings.get_https_everywhere_enabled_or(true);
+    settings.get_ease_mode_enabled_or(false);
+};
+
+#[cfg(feature="rewriter")]
+const _: fn(&mut Rewriter) = |rewriter| {
+    let _ = rewriter.rewrite_url("http://example.com/");
+};
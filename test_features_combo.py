@@ -18,8 +18,8 @@ def powerset(input):
     return subset + with_pivot
 
 
-def check(toolchain, features):
-    for subset in powerset(features):
+def check(toolchain, feature_sets):
+    for subset in feature_sets:
         feature_str = ",".join(subset)
         print("$ cargo +" + toolchain + " check --no-default-features --features " + feature_str)
 
@@ -44,9 +44,20 @@ features = [
     "rewriter",
     "updater",
     "add_rulesets",
+    "add_rulesets_gzip",
     "potentially_applicable",
     "settings",
     "get_simple_rules_ending_with",
 ]
 
-check("stable", features)
+# Features that only swap out or add to the implementation of the ones above are checked one at a
+# time on top of all of them, rather than in the powerset
+leaf_features = [
+    "arc-swap",
+    "cffi",
+    "wasm",
+    "async-storage",
+    "tracing",
+]
+
+check("stable", powerset(features) + [features + [leaf] for leaf in leaf_features])